@@ -0,0 +1,361 @@
+use std::{error::Error, path::PathBuf, process::ExitCode, time::Duration};
+
+use clap::{Args, Parser, Subcommand};
+use serde_json::json;
+use tracing::level_filters::LevelFilter;
+
+use crate::{config, control, discord::{self, api::cache}};
+
+#[derive(Parser)]
+#[command(version, about, args_conflicts_with_subcommands = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    #[command(flatten)]
+    pub run: RunArgs,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Run the switch (the default)
+    Run(RunArgs),
+    /// List Discord sockets and whether anything is listening on them
+    List {
+        #[command(flatten)]
+        config: ConfigArgs,
+        /// Directory to search for Discord sockets, in addition to those in the config
+        #[arg(long = "ipc-dir", value_name = "DIR")]
+        ipc_dirs: Vec<PathBuf>,
+        #[arg(long)]
+        json: bool,
+    },
+    /// Validate a config file
+    CheckConfig {
+        #[command(flatten)]
+        config: ConfigArgs,
+        #[arg(long)]
+        json: bool,
+    },
+    /// Clear and withhold activity on a Discord instance of the running switch
+    Mute {
+        /// Instance name, e.g. discord-ipc-1
+        instance: String,
+        /// How long to mute for, e.g. 90s, 30m or 1h, instead of until unmuted
+        #[arg(long = "for", value_name = "DURATION", value_parser = parse_duration)]
+        duration: Option<Duration>,
+        #[arg(long)]
+        json: bool,
+    },
+    /// Show activity on a muted Discord instance again
+    Unmute {
+        instance: String,
+        #[arg(long)]
+        json: bool,
+    },
+    /// Show cached application metadata, or clear it
+    Cache {
+        #[command(subcommand)]
+        action: Option<CacheAction>,
+        #[arg(long)]
+        json: bool,
+    },
+    /// Show the active profile, or switch to another
+    Profile {
+        /// Profile to switch to
+        #[arg(conflicts_with = "default")]
+        name: Option<String>,
+        /// Switch back to the config without any profile applied
+        #[arg(long)]
+        default: bool,
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print the version
+    Version {
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum CacheAction {
+    /// Forget one application's metadata, or every application's
+    Clear {
+        client_id: Option<String>,
+    },
+}
+
+#[derive(Args)]
+pub struct ConfigArgs {
+    /// Config file to use instead of the default one
+    #[arg(long, env = config::PATH_ENV)]
+    pub config: Option<PathBuf>,
+}
+
+#[derive(Args)]
+pub struct RunArgs {
+    #[command(flatten)]
+    pub config: ConfigArgs,
+    /// Log level, overriding the config
+    #[arg(long, value_name = "LEVEL")]
+    pub log_level: Option<LevelFilter>,
+    /// IPC name to listen on, overriding the config
+    #[arg(long)]
+    pub name: Option<String>,
+    /// Directory to search for Discord sockets, in addition to those in the config
+    #[arg(long = "ipc-dir", value_name = "DIR")]
+    pub ipc_dirs: Vec<PathBuf>,
+}
+
+impl RunArgs {
+    pub fn source(self) -> config::Source {
+        let overrides = config::Overrides {
+            name: self.name,
+            log_level: self.log_level,
+            ipc_dirs: self.ipc_dirs,
+        };
+
+        config::Source::locate(self.config.config, overrides)
+    }
+}
+
+pub fn list(config: ConfigArgs, ipc_dirs: Vec<PathBuf>, json: bool) -> Result<(), Box<dyn Error>> {
+    let overrides = config::Overrides {
+        ipc_dirs,
+        ..config::Overrides::default()
+    };
+    let config = config::Source::locate(config.config, overrides).load()?;
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+
+    let sockets = runtime.block_on(async {
+        let mut sockets = Vec::new();
+        for name in discord::ipc::names(&config.server.ipc_dirs) {
+            let alive = discord::ipc::probe(&name).await;
+            sockets.push((name, alive));
+        }
+        sockets
+    });
+
+    if json {
+        let sockets = sockets
+            .iter()
+            .map(|(name, alive)| json!({
+                "name": name,
+                "path": discord::ipc::path(name),
+                "alive": alive,
+            }))
+            .collect::<Vec<_>>();
+        println!("{}", serde_json::to_string_pretty(&sockets)?);
+        return Ok(());
+    }
+
+    if sockets.is_empty() {
+        println!("No Discord sockets found in {}", discord::ipc::dir().display());
+    }
+    for (name, alive) in sockets {
+        let state = if alive { "alive" } else { "stale" };
+        println!("{:<16} {:<6} {}", name.rsplit(['/', '\\']).next().unwrap_or(&name), state, discord::ipc::path(&name).display());
+    }
+
+    Ok(())
+}
+
+pub fn check_config(config: ConfigArgs, json: bool) -> ExitCode {
+    let source = config::Source::locate(config.config, config::Overrides::default());
+    let result = source.load();
+
+    if json {
+        let output = json!({
+            "path": source.path,
+            "valid": result.is_ok(),
+            "error": result.as_ref().err().map(ToString::to_string),
+        });
+        println!("{}", output);
+    } else {
+        match (&result, &source.path) {
+            (Ok(_), Some(path)) if path.exists() => println!("{} is valid", path.display()),
+            (Ok(_), _) => println!("No config file, using defaults"),
+            (Err(e), _) => eprintln!("{}", e),
+        }
+    }
+
+    match result {
+        Ok(_) => ExitCode::SUCCESS,
+        Err(_) => ExitCode::FAILURE,
+    }
+}
+
+/// Mutes or unmutes an instance through the control socket
+pub fn mute(instance: String, muted: bool, duration: Option<Duration>, json: bool) -> Result<(), Box<dyn Error>> {
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+    let result = runtime.block_on(async {
+        match muted {
+            true => {
+                let params = serde_json::json!({ "instance": instance, "seconds": duration.map(|duration| duration.as_secs()) });
+                control::request("mute_instance", params).await
+            }
+            false => control::request("unmute_instance", serde_json::json!({ "instance": instance })).await,
+        }
+    })?;
+
+    match (json, muted, duration) {
+        (true, ..) => println!("{}", result),
+        (false, true, Some(duration)) => println!("Muted {} for {}s", instance, duration.as_secs()),
+        (false, true, None) => println!("Muted {}", instance),
+        (false, false, _) => println!("Unmuted {}", instance),
+    }
+
+    Ok(())
+}
+
+/// Shows or clears the application metadata cache
+pub fn cache(action: Option<CacheAction>, json: bool) -> Result<(), Box<dyn Error>> {
+    let path = cache::path().ok_or("no cache directory, set XDG_CACHE_HOME or HOME")?;
+
+    if let Some(CacheAction::Clear { client_id }) = action {
+        let count = clear_cache(&path, client_id.as_deref())?;
+        match json {
+            true => println!("{}", json!({ "cleared": count })),
+            false => println!("Cleared {} application{}", count, if count == 1 { "" } else { "s" }),
+        }
+        return Ok(());
+    }
+
+    let store = cache::Store::load(&path);
+    let now = cache::now();
+    let applications = store
+        .client_ids()
+        .into_iter()
+        .map(|id| {
+            let application = store.applications.get(&id);
+            let assets = store.assets.get(&id);
+            let used = application.into_iter().map(|entry| entry.used).chain(assets.map(|entry| entry.used)).max();
+            json!({
+                "client_id": id,
+                "name": application.and_then(|entry| entry.value.as_ref()).map(|data| &data.name),
+                "known": application.map(|entry| entry.value.is_some()),
+                "assets": assets.and_then(|entry| entry.value.as_ref()).map(Vec::len),
+                "age": application.map(|entry| now.saturating_sub(entry.fetched)),
+                "idle": used.map(|used| now.saturating_sub(used)),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&applications)?);
+        return Ok(());
+    }
+
+    println!("{}", path.display());
+    if applications.is_empty() {
+        println!("No applications cached");
+    }
+    for application in applications {
+        let name = match application["known"].as_bool() {
+            Some(false) => "(unknown to Discord)",
+            _ => application["name"].as_str().unwrap_or("-"),
+        };
+        let assets = application["assets"].as_u64().map_or(String::from("-"), |count| count.to_string());
+        let age = application["age"].as_u64().map_or(String::from("-"), |age| format!("{}s", age));
+        println!("{:<20} {:<32} {:>6} assets  fetched {} ago", application["client_id"].as_str().unwrap_or_default(), name, assets, age);
+    }
+
+    Ok(())
+}
+
+/// Clears the cache through the running switch, which would otherwise write back what it holds,
+/// or directly when it isn't running
+fn clear_cache(path: &std::path::Path, client_id: Option<&str>) -> Result<u64, Box<dyn Error>> {
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+    let params = json!({ "client_id": client_id });
+    match runtime.block_on(control::request("clear_cache", params)) {
+        Ok(result) => return Ok(result["cleared"].as_u64().unwrap_or_default()),
+        Err(e) if not_running(e.as_ref()) => {}
+        Err(e) => return Err(e),
+    }
+
+    let mut store = cache::Store::load(path);
+    let count = store.clear(client_id);
+    store.save(path)?;
+    Ok(count as u64)
+}
+
+/// Whether a control request failed because no switch is listening
+fn not_running(e: &(dyn Error + 'static)) -> bool {
+    e.downcast_ref::<std::io::Error>()
+        .is_some_and(|e| matches!(e.kind(), std::io::ErrorKind::NotFound | std::io::ErrorKind::ConnectionRefused))
+}
+
+/// Shows or switches the active profile through the control socket
+pub fn profile(name: Option<String>, default: bool, json: bool) -> Result<(), Box<dyn Error>> {
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+    let result = runtime.block_on(async {
+        match (name, default) {
+            (None, false) => control::request("get_profile", serde_json::Value::Null).await,
+            (name, _) => control::request("set_profile", serde_json::json!({ "name": name })).await,
+        }
+    })?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&result)?);
+        return Ok(());
+    }
+
+    println!(
+        "Active profile: {} ({})",
+        result["active"].as_str().unwrap_or("(none)"),
+        result["trigger"].as_str().unwrap_or_default()
+    );
+    let profiles = result["profiles"].as_array().cloned().unwrap_or_default();
+    let names = profiles.iter().filter_map(|name| name.as_str()).collect::<Vec<_>>();
+    println!("Profiles: {}", if names.is_empty() { String::from("(none)") } else { names.join(", ") });
+
+    Ok(())
+}
+
+/// Parses a duration such as `90s`, `30m`, `1h` or a plain number of seconds
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    let (number, unit) = value.split_at(value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len()));
+    let number: u64 = number.parse().map_err(|_| format!("invalid duration {:?}", value))?;
+
+    let scale = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 60 * 60 * 24,
+        _ => return Err(format!("invalid duration unit {:?}, expected s, m, h or d", unit)),
+    };
+    let seconds = number.checked_mul(scale).ok_or_else(|| format!("duration {:?} is too long", value))?;
+
+    Ok(Duration::from_secs(seconds))
+}
+
+pub fn version(json: bool) {
+    let name = env!("CARGO_PKG_NAME");
+    let version = env!("CARGO_PKG_VERSION");
+
+    if json {
+        println!("{}", json!({ "name": name, "version": version }));
+    } else {
+        println!("{} {}", name, version);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_durations() {
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("90s"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("30m"), Ok(Duration::from_secs(30 * 60)));
+        assert_eq!(parse_duration("1h"), Ok(Duration::from_secs(60 * 60)));
+        assert_eq!(parse_duration("2d"), Ok(Duration::from_secs(2 * 24 * 60 * 60)));
+        assert!(parse_duration("1w").is_err());
+        assert!(parse_duration("h").is_err());
+        assert_eq!(parse_duration(&format!("{}", u64::MAX)), Ok(Duration::from_secs(u64::MAX)));
+        assert!(parse_duration(&format!("{}d", u64::MAX / 60)).is_err());
+        assert!(parse_duration("99999999999999999999").is_err());
+    }
+}
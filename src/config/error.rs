@@ -0,0 +1,37 @@
+use std::{fmt, path::PathBuf};
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(PathBuf, std::io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Invalid(PathBuf, String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Read(path, e) => write!(f, "unable to read {}: {}", path.display(), e),
+            ConfigError::Parse(path, e) => write!(f, "invalid config {}: {}", path.display(), e),
+            ConfigError::Invalid(path, e) => write!(f, "invalid config {}: {}", path.display(), e),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_error_display() {
+        let err = ConfigError::Read(
+            PathBuf::from("config.toml"),
+            std::io::Error::new(std::io::ErrorKind::PermissionDenied, "denied"),
+        );
+        assert_eq!(format!("{}", err), "unable to read config.toml: denied");
+
+        let err = ConfigError::Invalid(PathBuf::from("config.toml"), String::from("bad"));
+        assert_eq!(format!("{}", err), "invalid config config.toml: bad");
+    }
+}
@@ -0,0 +1,900 @@
+use std::{collections::HashMap, path::{Path, PathBuf}, time::Duration};
+
+use chrono::{Datelike, NaiveDateTime, NaiveTime, Weekday};
+use serde::{Deserialize, Deserializer};
+use tracing::level_filters::LevelFilter;
+
+use crate::config::error::ConfigError;
+
+pub mod error;
+pub mod watch;
+
+const FILE_NAME: &str = "config.toml";
+
+/// Environment variable naming a config file to use instead of the default one
+pub const PATH_ENV: &str = "PRESENCE_SWITCH_CONFIG";
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: Server,
+    pub log: Log,
+    pub api: Api,
+    pub cache: Cache,
+    pub applications: Applications,
+    pub commands: Commands,
+    pub routing: Routing,
+    /// Default arbitration between applications showing an activity at the same time
+    pub arbitration: Arbitration,
+    /// Per-instance settings, keyed by IPC name (e.g. `discord-ipc-1`)
+    pub instances: HashMap<String, Instance>,
+    pub activity: Activity,
+    pub limits: Limits,
+    pub peer: Peer,
+    pub sandbox: Sandbox,
+    /// Instances activity is never shown on
+    pub mute: Vec<String>,
+    /// Profile active at startup
+    pub profile: Option<String>,
+    /// Named sets of settings that replace the ones above while active
+    pub profiles: HashMap<String, Profile>,
+    pub auto_profile: AutoProfile,
+}
+
+/// The switch's own IPC
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Server {
+    /// IPC name to listen on if available, otherwise the next free one is used
+    pub name: String,
+    /// Frames queued for each Discord instance before further ones are refused
+    pub queue_capacity: usize,
+    /// Directories searched for Discord sockets besides the runtime directory, such as those of
+    /// sandboxed Discord installs
+    pub ipc_dirs: Vec<PathBuf>,
+}
+
+impl Default for Server {
+    fn default() -> Self {
+        Server {
+            name: String::from("discord-ipc-0"),
+            queue_capacity: 16,
+            ipc_dirs: Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Log {
+    #[serde(deserialize_with = "level_filter")]
+    pub level: LevelFilter,
+}
+
+impl Default for Log {
+    fn default() -> Self {
+        Log { level: LevelFilter::TRACE }
+    }
+}
+
+fn level_filter<'de, D: Deserializer<'de>>(deserializer: D) -> Result<LevelFilter, D::Error> {
+    String::deserialize(deserializer)?.parse().map_err(serde::de::Error::custom)
+}
+
+/// The Discord REST API, used to look up application metadata
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Api {
+    /// Never contact the API, using only metadata already cached
+    pub offline: bool,
+    pub base_url: String,
+    /// Seconds each request may take
+    pub timeout: u64,
+    /// Requests made for each lookup before giving up, waiting twice as long after each failure
+    pub attempts: u32,
+}
+
+impl Default for Api {
+    fn default() -> Self {
+        Api {
+            offline: false,
+            base_url: String::from("https://discord.com/api/v9"),
+            timeout: 5,
+            attempts: 4,
+        }
+    }
+}
+
+/// On-disk cache of application metadata fetched from the Discord API
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Cache {
+    /// Seconds metadata is used before it is fetched again
+    pub ttl: u64,
+    /// Seconds an application ID unknown to Discord is remembered
+    pub negative_ttl: u64,
+    /// Applications kept, evicting the least recently used
+    pub capacity: usize,
+}
+
+impl Default for Cache {
+    fn default() -> Self {
+        Cache {
+            ttl: 24 * 60 * 60,
+            negative_ttl: 60 * 60,
+            capacity: 256,
+        }
+    }
+}
+
+/// Which RPC applications are relayed, keyed by `client_id`
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Applications {
+    /// When non-empty, only these applications are relayed and all others are blocked
+    pub allow: Vec<String>,
+    /// Applications refused with a Close frame
+    pub block: Vec<String>,
+    /// Applications acknowledged locally but never relayed to Discord
+    pub swallow: Vec<String>,
+}
+
+/// Which Discord instances receive each RPC command
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Commands {
+    /// Commands relayed to every Discord instance
+    pub broadcast: Vec<String>,
+    /// Instance that receives all other commands; when unset they are denied
+    pub instance: Option<String>,
+    /// Commands that are never relayed
+    pub deny: Vec<String>,
+    /// Seconds to wait for a Discord instance to answer a command
+    pub timeout: u64,
+}
+
+impl Default for Commands {
+    fn default() -> Self {
+        let broadcast = [
+            "SET_ACTIVITY",
+            "SEND_ACTIVITY_JOIN_INVITE",
+            "CLOSE_ACTIVITY_REQUEST",
+            "SUBSCRIBE",
+            "UNSUBSCRIBE",
+        ];
+
+        Commands {
+            broadcast: broadcast.iter().map(|cmd| cmd.to_string()).collect(),
+            instance: None,
+            deny: Vec::new(),
+            timeout: 10,
+        }
+    }
+}
+
+/// Which Discord instance answers read commands such as `GET_GUILDS`
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Routing {
+    /// Instance answering read commands; defaults to `commands.instance`
+    pub instance: Option<String>,
+    /// Per-application overrides, keyed by `client_id`
+    pub applications: HashMap<String, ApplicationRouting>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ApplicationRouting {
+    /// Instance answering this application's read commands
+    pub instance: Option<String>,
+    /// Times this application's activity is withheld from every instance
+    pub quiet: Vec<Window>,
+}
+
+impl Routing {
+    /// Instance designated to answer read commands from an application
+    pub fn read_instance<'a>(&'a self, commands: &'a Commands, client_id: &str) -> Option<&'a String> {
+        self.applications
+            .get(client_id)
+            .and_then(|app| app.instance.as_ref())
+            .or(self.instance.as_ref())
+            .or(commands.instance.as_ref())
+    }
+}
+
+/// Decides which application's activity a Discord instance shows when several have one
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(tag = "policy", rename_all = "lowercase")]
+pub enum Arbitration {
+    /// The most recently set activity is shown
+    #[default]
+    Recent,
+    /// The first listed application with an activity is shown, then the most recent
+    Priority { applications: Vec<String> },
+    /// The application is shown whenever it has an activity, otherwise the most recent
+    Pinned { application: String },
+}
+
+/// How `SET_ACTIVITY` is relayed
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Activity {
+    /// Minimum seconds between activity updates sent to an instance; newer updates are held and
+    /// only the latest is sent when the window closes
+    pub window: u64,
+    /// Seconds without any frame from a client before its activity is cleared, or 0 to keep it
+    pub idle_timeout: u64,
+    /// Per-application `idle_timeout` overrides, keyed by `client_id`
+    pub idle_timeouts: HashMap<String, u64>,
+    /// `pid` activity is relayed with, unless overridden per instance
+    pub pid: Pid,
+    pub validation: Validation,
+    pub assets: Assets,
+}
+
+/// Checks image keys against the assets uploaded to each application
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Assets {
+    pub unknown: UnknownAssets,
+    /// Key put in place of unknown ones when `unknown` is `replace`
+    pub replacement: Option<String>,
+}
+
+/// What happens to an image key the application has no asset for
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum UnknownAssets {
+    /// Not checked, so assets aren't fetched
+    Off,
+    /// Logged once per session
+    #[default]
+    Warn,
+    /// Removed, leaving no image
+    Strip,
+    /// Replaced with `replacement`
+    Replace,
+}
+
+/// What happens to activity Discord would reject, such as text that is too long or too many buttons
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Validation {
+    /// Relayed as sent, leaving each instance to answer with its own error
+    Off,
+    /// Answered with a single error and not relayed
+    #[default]
+    Reject,
+    /// Repaired and relayed
+    Fix,
+}
+
+/// Which `pid` a `SET_ACTIVITY` is relayed with. Discord ties activities to it, so a pid from
+/// inside a container, VM or Wine prefix can get activities cleared or merged.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Pid {
+    /// The pid the client sent
+    #[default]
+    Client,
+    /// The pid of the process connected to the switch, where known
+    Peer,
+    /// A pid unique to the session that no real process can have
+    Session,
+}
+
+impl Default for Activity {
+    fn default() -> Self {
+        Activity {
+            // Discord allows 5 updates every 20 seconds
+            window: 4,
+            idle_timeout: 0,
+            idle_timeouts: HashMap::new(),
+            pid: Pid::default(),
+            validation: Validation::default(),
+            assets: Assets::default(),
+        }
+    }
+}
+
+impl Activity {
+    /// How long an application may go without sending a frame before its activity is cleared
+    pub fn idle_timeout(&self, client_id: &str) -> Option<Duration> {
+        let seconds = self.idle_timeouts.get(client_id).copied().unwrap_or(self.idle_timeout);
+        (seconds > 0).then(|| Duration::from_secs(seconds))
+    }
+}
+
+/// Protects the switch from buggy or hostile RPC clients
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    /// Largest frame payload a client may send, in bytes
+    pub frame_size: usize,
+    /// Frames a client may send each second, or 0 for no limit
+    pub frames_per_second: u32,
+    /// Clients that may be connected at once, or 0 for no limit
+    pub sessions: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            frame_size: 64 * 1024,
+            frames_per_second: 100,
+            sessions: 32,
+        }
+    }
+}
+
+/// Local users besides our own that may connect to the switch socket
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Peer {
+    /// User IDs allowed to connect
+    pub uids: Vec<u32>,
+    /// Group IDs whose members may connect
+    pub gids: Vec<u32>,
+}
+
+/// Opt-in hardening of the daemon itself, currently Landlock and seccomp on Linux
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Sandbox {
+    pub enabled: bool,
+}
+
+/// Settings a profile replaces while it is active; each section given replaces the whole section
+/// of the base config
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Profile {
+    pub applications: Option<Applications>,
+    pub commands: Option<Commands>,
+    pub routing: Option<Routing>,
+    pub arbitration: Option<Arbitration>,
+    pub instances: Option<HashMap<String, Instance>>,
+    pub mute: Option<Vec<String>>,
+}
+
+/// Activates profiles while certain processes are running
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AutoProfile {
+    /// Seconds between scans of running processes
+    pub interval: u64,
+    /// Seconds the matching rule must stay the same before its profile is activated
+    pub debounce: u64,
+    pub rules: Vec<ProfileRule>,
+}
+
+impl Default for AutoProfile {
+    fn default() -> Self {
+        AutoProfile {
+            interval: 5,
+            debounce: 10,
+            rules: Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProfileRule {
+    pub profile: String,
+    /// Process names, any of which running activates the profile
+    pub processes: Vec<String>,
+    /// When several rules match, the highest priority wins, then the first listed
+    #[serde(default)]
+    pub priority: i32,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Instance {
+    pub arbitration: Option<Arbitration>,
+    /// Times activity is withheld from this instance
+    pub quiet: Vec<Window>,
+    /// When non-empty, activity is only shown on this instance during these times
+    pub hours: Vec<Window>,
+    pub pid: Option<Pid>,
+}
+
+/// A weekly span of local time, such as weekdays from 09:00 to 17:00
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Window {
+    /// Days the window starts on, every day if empty
+    #[serde(default, deserialize_with = "weekdays")]
+    pub days: Vec<Weekday>,
+    #[serde(deserialize_with = "time_of_day")]
+    pub start: NaiveTime,
+    /// End of the window, on the next day if not after `start`
+    #[serde(deserialize_with = "time_of_day")]
+    pub end: NaiveTime,
+}
+
+impl Window {
+    /// Whether a local time falls within the window
+    pub fn contains(&self, at: NaiveDateTime) -> bool {
+        let starts_on = |day: Weekday| self.days.is_empty() || self.days.contains(&day);
+        let (day, time) = (at.weekday(), at.time());
+
+        if self.start < self.end {
+            starts_on(day) && self.start <= time && time < self.end
+        } else {
+            (starts_on(day) && self.start <= time) || (starts_on(day.pred()) && time < self.end)
+        }
+    }
+}
+
+fn weekdays<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Weekday>, D::Error> {
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|day| day.parse().map_err(|_| serde::de::Error::custom(format!("invalid day {:?}", day))))
+        .collect()
+}
+
+fn time_of_day<'de, D: Deserializer<'de>>(deserializer: D) -> Result<NaiveTime, D::Error> {
+    let time = String::deserialize(deserializer)?;
+    NaiveTime::parse_from_str(&time, "%H:%M").map_err(|_| serde::de::Error::custom(format!("invalid time {:?}, expected HH:MM", time)))
+}
+
+impl Config {
+    /// Arbitration policy for an instance
+    pub fn arbitration(&self, instance: &str) -> &Arbitration {
+        self.instances
+            .get(instance)
+            .and_then(|instance| instance.arbitration.as_ref())
+            .unwrap_or(&self.arbitration)
+    }
+
+    /// `pid` activity is relayed to an instance with
+    pub fn pid(&self, instance: &str) -> Pid {
+        self.instances
+            .get(instance)
+            .and_then(|instance| instance.pid)
+            .unwrap_or(self.activity.pid)
+    }
+
+    /// The config with a profile's settings in place, or `None` if there is no such profile
+    pub fn with_profile(&self, name: &str) -> Option<Config> {
+        let profile = self.profiles.get(name)?.clone();
+        let mut config = self.clone();
+
+        if let Some(applications) = profile.applications {
+            config.applications = applications;
+        }
+        if let Some(commands) = profile.commands {
+            config.commands = commands;
+        }
+        if let Some(routing) = profile.routing {
+            config.routing = routing;
+        }
+        if let Some(arbitration) = profile.arbitration {
+            config.arbitration = arbitration;
+        }
+        if let Some(instances) = profile.instances {
+            config.instances = instances;
+        }
+        if let Some(mute) = profile.mute {
+            config.mute = mute;
+        }
+
+        Some(config)
+    }
+
+    pub fn parse(contents: &str) -> Result<Config, toml::de::Error> {
+        toml::from_str(contents)
+    }
+
+    /// Checks settings that parse but cannot work
+    pub fn validate(&self) -> Result<(), String> {
+        if self.server.name.is_empty() || self.server.name.contains(['/', '\\']) {
+            return Err(format!("server.name {:?} is not a valid IPC name", self.server.name));
+        }
+        if self.server.queue_capacity == 0 {
+            return Err(String::from("server.queue_capacity must be at least 1"));
+        }
+        if !self.api.base_url.starts_with("https://") && !self.api.base_url.starts_with("http://") {
+            return Err(format!("api.base_url {:?} must be an http or https URL", self.api.base_url));
+        }
+        if self.commands.timeout == 0 {
+            return Err(String::from("commands.timeout must be at least 1"));
+        }
+        if self.api.timeout == 0 || self.api.attempts == 0 {
+            return Err(String::from("api.timeout and api.attempts must be at least 1"));
+        }
+        if self.limits.frame_size == 0 {
+            return Err(String::from("limits.frame_size must be at least 1"));
+        }
+        if let Some(profile) = &self.profile
+            && !self.profiles.contains_key(profile)
+        {
+            return Err(format!("profile {:?} is not defined in [profiles]", profile));
+        }
+        if self.activity.assets.unknown == UnknownAssets::Replace && self.activity.assets.replacement.is_none() {
+            return Err(String::from("activity.assets.replacement is required when unknown is \"replace\""));
+        }
+        if self.auto_profile.interval == 0 {
+            return Err(String::from("auto_profile.interval must be at least 1"));
+        }
+        if let Some(rule) = self.auto_profile.rules.iter().find(|rule| !self.profiles.contains_key(&rule.profile)) {
+            return Err(format!("auto_profile rule uses undefined profile {:?}", rule.profile));
+        }
+
+        Ok(())
+    }
+}
+
+/// Settings given on the command line, which take precedence over the file on every load
+#[derive(Clone, Debug, Default)]
+pub struct Overrides {
+    pub name: Option<String>,
+    pub log_level: Option<LevelFilter>,
+    pub ipc_dirs: Vec<PathBuf>,
+}
+
+impl Overrides {
+    fn apply(&self, config: &mut Config) {
+        if let Some(name) = &self.name {
+            config.server.name = name.clone();
+        }
+        if let Some(level) = self.log_level {
+            config.log.level = level;
+        }
+        config.server.ipc_dirs.extend(self.ipc_dirs.iter().cloned());
+    }
+}
+
+/// Where the config is loaded from
+#[derive(Clone, Debug, Default)]
+pub struct Source {
+    pub path: Option<PathBuf>,
+    /// Whether the path was given by flag or environment, in which case the file must exist
+    pub explicit: bool,
+    pub overrides: Overrides,
+}
+
+impl Source {
+    /// Uses `path` if given, otherwise the default location
+    pub fn locate(path: Option<PathBuf>, overrides: Overrides) -> Source {
+        Source {
+            explicit: path.is_some(),
+            path: path.or_else(self::path),
+            overrides,
+        }
+    }
+
+    /// Loads and validates the config, falling back to defaults when a default location is empty
+    pub fn load(&self) -> Result<Config, ConfigError> {
+        let mut config = self.read()?;
+        self.overrides.apply(&mut config);
+
+        let path = self.path.clone().unwrap_or_default();
+        config.validate().map_err(|e| ConfigError::Invalid(path, e))?;
+
+        Ok(config)
+    }
+
+    fn read(&self) -> Result<Config, ConfigError> {
+        let Some(path) = &self.path else {
+            return Ok(Config::default());
+        };
+
+        match std::fs::read_to_string(path) {
+            Ok(contents) => {
+                tracing::info!("Loading config from {}", path.display());
+                Config::parse(&contents).map_err(|e| ConfigError::Parse(path.clone(), e))
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && !self.explicit => Ok(Config::default()),
+            Err(e) => Err(ConfigError::Read(path.clone(), e)),
+        }
+    }
+
+    /// Directory holding the config file
+    pub fn dir(&self) -> Option<&Path> {
+        self.path.as_deref().and_then(Path::parent)
+    }
+}
+
+/// Directory holding presence-switch's config file
+pub fn dir() -> Option<PathBuf> {
+    #[cfg(unix)]
+    let base = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")));
+
+    #[cfg(windows)]
+    let base = std::env::var_os("APPDATA").map(PathBuf::from);
+
+    base.map(|dir| dir.join(env!("CARGO_PKG_NAME")))
+}
+
+pub fn path() -> Option<PathBuf> {
+    dir().map(|dir| dir.join(FILE_NAME))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_empty() {
+        let config = Config::parse("").unwrap();
+        assert!(config.applications.allow.is_empty());
+        assert!(config.applications.block.is_empty());
+        assert!(config.applications.swallow.is_empty());
+    }
+
+    #[test]
+    fn parse_applications() {
+        let config = Config::parse(
+            r#"
+            [applications]
+            block = ["1"]
+            swallow = ["2", "3"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.applications.block, vec!["1"]);
+        assert_eq!(config.applications.swallow, vec!["2", "3"]);
+    }
+
+    #[test]
+    fn parse_commands() {
+        let config = Config::parse("").unwrap();
+        assert!(config.commands.broadcast.contains(&String::from("SET_ACTIVITY")));
+        assert!(config.commands.instance.is_none());
+        assert_eq!(config.commands.timeout, 10);
+
+        let config = Config::parse(
+            r#"
+            [commands]
+            instance = "discord-ipc-1"
+            deny = ["AUTHORIZE"]
+            "#,
+        )
+        .unwrap();
+        assert!(config.commands.broadcast.contains(&String::from("SET_ACTIVITY")));
+        assert_eq!(config.commands.instance.as_deref(), Some("discord-ipc-1"));
+        assert_eq!(config.commands.deny, vec!["AUTHORIZE"]);
+    }
+
+    #[test]
+    fn routing_read_instance() {
+        let config = Config::parse(
+            r#"
+            [commands]
+            instance = "discord-ipc-1"
+
+            [routing.applications.123]
+            instance = "discord-ipc-3"
+            "#,
+        )
+        .unwrap();
+        let read_instance = |client_id| config.routing.read_instance(&config.commands, client_id).cloned();
+        assert_eq!(read_instance("123").as_deref(), Some("discord-ipc-3"));
+        assert_eq!(read_instance("456").as_deref(), Some("discord-ipc-1"));
+
+        let config = Config::parse("[routing]\ninstance = \"discord-ipc-2\"").unwrap();
+        assert_eq!(config.routing.read_instance(&config.commands, "456").map(String::as_str), Some("discord-ipc-2"));
+
+        let config = Config::parse("").unwrap();
+        assert!(config.routing.read_instance(&config.commands, "456").is_none());
+    }
+
+    #[test]
+    fn parse_arbitration() {
+        let config = Config::parse(
+            r#"
+            [arbitration]
+            policy = "priority"
+            applications = ["1", "2"]
+
+            [instances.discord-ipc-2.arbitration]
+            policy = "pinned"
+            application = "3"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.arbitration("discord-ipc-1"),
+            &Arbitration::Priority {
+                applications: vec![String::from("1"), String::from("2")]
+            }
+        );
+        assert_eq!(
+            config.arbitration("discord-ipc-2"),
+            &Arbitration::Pinned {
+                application: String::from("3")
+            }
+        );
+
+        let config = Config::parse("").unwrap();
+        assert_eq!(config.arbitration("discord-ipc-1"), &Arbitration::Recent);
+
+        assert!(Config::parse("[arbitration]\npolicy = \"pinned\"").is_err());
+    }
+
+    #[test]
+    fn parse_activity() {
+        assert_eq!(Config::parse("").unwrap().activity.window, 4);
+        assert_eq!(Config::parse("[activity]\nwindow = 0").unwrap().activity.window, 0);
+
+        let activity = Config::parse("[activity]\nidle_timeout = 600\n[activity.idle_timeouts]\n123 = 60\n456 = 0").unwrap().activity;
+        assert_eq!(activity.idle_timeout("123"), Some(Duration::from_secs(60)));
+        assert_eq!(activity.idle_timeout("456"), None);
+        assert_eq!(activity.idle_timeout("789"), Some(Duration::from_secs(600)));
+        assert_eq!(Activity::default().idle_timeout("123"), None);
+
+        let config = Config::parse("[activity]\npid = \"peer\"\n[instances.discord-ipc-2]\npid = \"session\"").unwrap();
+        assert_eq!(config.pid("discord-ipc-1"), Pid::Peer);
+        assert_eq!(config.pid("discord-ipc-2"), Pid::Session);
+        assert!(Config::parse("[activity]\npid = \"host\"").is_err());
+    }
+
+    #[test]
+    fn parse_limits() {
+        let config = Config::parse("[limits]\nframe_size = 1024").unwrap();
+        assert_eq!(config.limits.frame_size, 1024);
+        assert_eq!(config.limits.frames_per_second, 100);
+        assert_eq!(config.limits.sessions, 32);
+    }
+
+    #[test]
+    fn parse_peer() {
+        let config = Config::parse("[peer]\nuids = [1001]\ngids = [100]").unwrap();
+        assert_eq!(config.peer.uids, vec![1001]);
+        assert_eq!(config.peer.gids, vec![100]);
+    }
+
+    #[test]
+    fn parse_sandbox() {
+        assert!(!Config::parse("").unwrap().sandbox.enabled);
+        assert!(Config::parse("[sandbox]\nenabled = true").unwrap().sandbox.enabled);
+    }
+
+    #[test]
+    fn parse_server_log_api() {
+        let config = Config::parse("[server]\nname = \"discord-ipc-9\"\n[log]\nlevel = \"warn\"\n[api]\noffline = true\nbase_url = \"http://localhost\"").unwrap();
+        assert_eq!(config.server.name, "discord-ipc-9");
+        assert_eq!(config.server.queue_capacity, 16);
+        assert_eq!(config.log.level, LevelFilter::WARN);
+        assert_eq!(config.api.base_url, "http://localhost");
+        assert!(config.api.offline);
+
+        assert!(Config::parse("[log]\nlevel = \"loud\"").is_err());
+    }
+
+    #[test]
+    fn validate() {
+        assert!(Config::default().validate().is_ok());
+
+        let config = Config::parse("[server]\nqueue_capacity = 0").unwrap();
+        assert!(config.validate().unwrap_err().contains("queue_capacity"));
+
+        let config = Config::parse("[api]\nbase_url = \"discord.com\"").unwrap();
+        assert!(config.validate().unwrap_err().contains("base_url"));
+
+        let config = Config::parse("[server]\nname = \"../discord-ipc-0\"").unwrap();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn load_explicit_missing() {
+        let source = Source {
+            path: Some(PathBuf::from("/nonexistent/config.toml")),
+            explicit: true,
+            ..Source::default()
+        };
+        assert!(matches!(source.load(), Err(ConfigError::Read(..))));
+
+        let source = Source { explicit: false, ..source };
+        assert!(source.load().is_ok());
+    }
+
+    #[test]
+    fn load_overrides() {
+        let source = Source {
+            overrides: Overrides {
+                name: Some(String::from("discord-ipc-5")),
+                log_level: Some(LevelFilter::DEBUG),
+                ipc_dirs: vec![PathBuf::from("/run/user/1000/snap.discord")],
+            },
+            ..Source::default()
+        };
+        let config = source.load().unwrap();
+        assert_eq!(config.server.name, "discord-ipc-5");
+        assert_eq!(config.log.level, LevelFilter::DEBUG);
+        assert_eq!(config.server.ipc_dirs.len(), 1);
+
+        let source = Source {
+            overrides: Overrides { name: Some(String::new()), ..Overrides::default() },
+            ..Source::default()
+        };
+        assert!(matches!(source.load(), Err(ConfigError::Invalid(..))));
+    }
+
+    #[test]
+    fn window_contains() {
+        let config = Config::parse(
+            r#"
+            [instances.discord-ipc-1]
+            hours = [{ days = ["mon", "tue", "wed", "thu", "fri"], start = "09:00", end = "17:00" }]
+            quiet = [{ days = ["fri"], start = "22:00", end = "07:00" }]
+            "#,
+        )
+        .unwrap();
+        let instance = &config.instances["discord-ipc-1"];
+        // 2026-10-16 is a Friday
+        let at = |day: u32, time: &str| {
+            chrono::NaiveDate::from_ymd_opt(2026, 10, day).unwrap().and_time(NaiveTime::parse_from_str(time, "%H:%M").unwrap())
+        };
+
+        assert!(instance.hours[0].contains(at(16, "09:00")));
+        assert!(!instance.hours[0].contains(at(16, "17:00")));
+        assert!(!instance.hours[0].contains(at(17, "12:00")));
+
+        // Spanning midnight, ending on the day after one listed
+        assert!(instance.quiet[0].contains(at(16, "23:00")));
+        assert!(instance.quiet[0].contains(at(17, "06:59")));
+        assert!(!instance.quiet[0].contains(at(16, "06:00")));
+
+        assert!(Config::parse("[instances.a]\nquiet = [{ start = \"9am\", end = \"17:00\" }]").is_err());
+        assert!(Config::parse("[instances.a]\nquiet = [{ days = [\"someday\"], start = \"09:00\", end = \"17:00\" }]").is_err());
+    }
+
+    #[test]
+    fn with_profile() {
+        let config = Config::parse(
+            r#"
+            mute = ["discord-ipc-2"]
+            [applications]
+            block = ["1"]
+            [commands]
+            instance = "discord-ipc-1"
+            [profiles.streaming]
+            mute = ["discord-ipc-1", "discord-ipc-2"]
+            [profiles.streaming.applications]
+            swallow = ["2"]
+            "#,
+        )
+        .unwrap();
+
+        let streaming = config.with_profile("streaming").unwrap();
+        assert_eq!(streaming.mute, vec!["discord-ipc-1", "discord-ipc-2"]);
+        // Sections the profile gives replace the base ones entirely
+        assert!(streaming.applications.block.is_empty());
+        assert_eq!(streaming.applications.swallow, vec!["2"]);
+        // Others are kept
+        assert_eq!(streaming.commands.instance.as_deref(), Some("discord-ipc-1"));
+
+        assert!(config.with_profile("work").is_none());
+    }
+
+    #[test]
+    fn validate_profile() {
+        let config = Config::parse("profile = \"work\"").unwrap();
+        assert!(config.validate().unwrap_err().contains("work"));
+
+        let config = Config::parse("profile = \"work\"\n[profiles.work]").unwrap();
+        assert!(config.validate().is_ok());
+
+        let config = Config::parse("[[auto_profile.rules]]\nprofile = \"streaming\"\nprocesses = [\"obs\"]").unwrap();
+        assert!(config.validate().unwrap_err().contains("streaming"));
+    }
+
+    #[test]
+    fn parse_unknown_field() {
+        assert!(Config::parse("[applications]\nblocked = []").is_err());
+    }
+
+    #[test]
+    fn path_ends_with_file_name() {
+        if let Some(path) = path() {
+            assert!(path.ends_with("presence-switch/config.toml"));
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use std::{path::{Path, PathBuf}, time::{Duration, SystemTime}};
+
+use tokio_util::sync::CancellationToken;
+
+/// How often the config file is checked for changes
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Calls `reload` whenever the config file changes or the process receives SIGHUP, until cancelled
+pub async fn watch(path: Option<PathBuf>, token: CancellationToken, reload: impl Fn()) {
+    let mut modified = Modified::new(path.as_deref());
+    let mut hangup = Hangup::new();
+    let mut interval = tokio::time::interval(POLL_INTERVAL);
+
+    loop {
+        tokio::select! {
+            _ = token.cancelled() => break,
+
+            _ = interval.tick() => {
+                if !path.as_deref().is_some_and(|path| modified.changed(path)) {
+                    continue;
+                }
+                tracing::info!("Config file changed, reloading");
+            }
+
+            _ = hangup.recv() => tracing::info!("Received SIGHUP, reloading config"),
+        }
+
+        reload();
+    }
+}
+
+/// Last modification time seen for a file, if it existed
+#[derive(Debug)]
+struct Modified(Option<SystemTime>);
+
+impl Modified {
+    fn new(path: Option<&Path>) -> Modified {
+        Modified(path.and_then(modified))
+    }
+
+    /// Whether the file was modified, created or removed since last checked
+    fn changed(&mut self, path: &Path) -> bool {
+        let modified = modified(path);
+        if modified == self.0 {
+            return false;
+        }
+
+        self.0 = modified;
+        true
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+/// Resolves on each SIGHUP, or never where there is no such signal
+struct Hangup {
+    #[cfg(unix)]
+    signal: Option<tokio::signal::unix::Signal>,
+}
+
+impl Hangup {
+    fn new() -> Hangup {
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{SignalKind, signal};
+
+            let signal = signal(SignalKind::hangup())
+                .inspect_err(|e| tracing::error!("Unable to listen for SIGHUP: {}", e))
+                .ok();
+            Hangup { signal }
+        }
+
+        #[cfg(windows)]
+        Hangup {}
+    }
+
+    async fn recv(&mut self) {
+        #[cfg(unix)]
+        if let Some(signal) = &mut self.signal {
+            signal.recv().await;
+            return;
+        }
+
+        std::future::pending::<()>().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use super::*;
+
+    #[test]
+    fn modified_changes() {
+        let path = std::env::temp_dir().join(format!("presence-switch-watch-{}.toml", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut modified = Modified::new(Some(&path));
+        assert!(!modified.changed(&path));
+
+        // Created
+        let file = File::create(&path).unwrap();
+        file.set_modified(SystemTime::UNIX_EPOCH).unwrap();
+        assert!(modified.changed(&path));
+        assert!(!modified.changed(&path));
+
+        // Modified
+        file.set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(1)).unwrap();
+        assert!(modified.changed(&path));
+
+        // Removed
+        std::fs::remove_file(&path).unwrap();
+        assert!(modified.changed(&path));
+    }
+}
@@ -0,0 +1,56 @@
+use std::fmt;
+
+use crate::{config::error::ConfigError, switch::registry::SessionId};
+
+#[derive(Debug)]
+pub enum ControlError {
+    Parse(serde_json::Error),
+    MethodNotFound(String),
+    InvalidParams(serde_json::Error),
+    NoSuchSession(SessionId),
+    NoSuchProfile(String),
+    Reload(ConfigError),
+    /// The switch answered a request with an error
+    Failed(String),
+}
+
+impl ControlError {
+    /// JSON-RPC error code
+    pub fn code(&self) -> i64 {
+        match self {
+            ControlError::Parse(_) => -32700,
+            ControlError::MethodNotFound(_) => -32601,
+            ControlError::InvalidParams(_) => -32602,
+            ControlError::NoSuchSession(_) | ControlError::NoSuchProfile(_) | ControlError::Reload(_) | ControlError::Failed(_) => -32000,
+        }
+    }
+}
+
+impl fmt::Display for ControlError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ControlError::Parse(e) => write!(f, "invalid request: {}", e),
+            ControlError::MethodNotFound(method) => write!(f, "unknown method {}", method),
+            ControlError::InvalidParams(e) => write!(f, "invalid params: {}", e),
+            ControlError::NoSuchSession(id) => write!(f, "no session {}", id),
+            ControlError::NoSuchProfile(name) => write!(f, "no profile {:?}", name),
+            ControlError::Reload(e) => write!(f, "{}", e),
+            ControlError::Failed(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for ControlError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn control_error_display() {
+        let err = ControlError::MethodNotFound(String::from("explode"));
+        assert_eq!(format!("{}", err), "unknown method explode");
+        assert_eq!(err.code(), -32601);
+        assert_eq!(format!("{}", ControlError::NoSuchSession(3)), "no session 3");
+    }
+}
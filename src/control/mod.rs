@@ -0,0 +1,317 @@
+use std::{error::Error, time::{Duration, Instant}};
+
+use serde::Deserialize;
+use serde_json::{Value, json};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+
+use crate::{control::error::ControlError, discord, switch::{ipc::{Server, Trigger}, registry::SessionId}};
+
+pub mod error;
+
+#[cfg(unix)]
+mod unix;
+
+#[cfg(windows)]
+mod windows;
+
+/// Name of the control socket, created next to the switch's IPC socket
+const NAME: &str = "presence-switch-control";
+
+/// A JSON-RPC 2.0 request
+#[derive(Deserialize)]
+struct Request {
+    #[serde(default)]
+    id: Value,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Deserialize)]
+struct CacheParams {
+    /// Application to forget, or all of them if absent
+    client_id: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct InstanceParams {
+    instance: String,
+}
+
+#[derive(Deserialize)]
+struct MuteParams {
+    instance: String,
+    /// How long the mute lasts, or until unmuted if absent
+    seconds: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct ProfileParams {
+    /// Profile to activate, or the base config if null
+    name: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct SessionParams {
+    session: SessionId,
+}
+
+/// Serves the control socket until the server is cancelled
+pub async fn start(server: Server) -> Result<(), Box<dyn Error>> {
+    #[cfg(unix)]
+    unix::start(server).await?;
+
+    #[cfg(windows)]
+    windows::start(server).await?;
+
+    Ok(())
+}
+
+/// Sends a request to the running switch, returning its result
+pub async fn request(method: &str, params: Value) -> Result<Value, Box<dyn Error>> {
+    let request = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+
+    #[cfg(unix)]
+    let response = unix::request(&request.to_string()).await?;
+
+    #[cfg(windows)]
+    let response = windows::request(&request.to_string()).await?;
+
+    let mut response: Value = serde_json::from_str(&response)?;
+    match response["error"]["message"].as_str() {
+        Some(message) => Err(Box::new(ControlError::Failed(message.to_owned()))),
+        None => Ok(response["result"].take()),
+    }
+}
+
+/// Answers each line-delimited request read from a control client
+async fn serve<R: AsyncRead + Unpin, W: AsyncWrite + Unpin>(server: Server, reader: R, mut writer: W) -> std::io::Result<()> {
+    let mut lines = BufReader::new(reader).lines();
+
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let mut response = respond(&server, &line).await.to_string();
+        response.push('\n');
+        writer.write_all(response.as_bytes()).await?;
+    }
+
+    Ok(())
+}
+
+async fn respond(server: &Server, line: &str) -> Value {
+    let (id, result) = match serde_json::from_str::<Request>(line) {
+        Ok(request) => (request.id, call(server, &request.method, request.params).await),
+        Err(e) => (Value::Null, Err(ControlError::Parse(e))),
+    };
+
+    match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(e) => {
+            tracing::warn!("Control request failed: {}", e);
+            json!({ "jsonrpc": "2.0", "id": id, "error": { "code": e.code(), "message": e.to_string() } })
+        }
+    }
+}
+
+async fn call(server: &Server, method: &str, params: Value) -> Result<Value, ControlError> {
+    tracing::debug!("Control request {}", method);
+
+    match method {
+        "list_sessions" => Ok(list_sessions(server)),
+        "list_instances" => Ok(list_instances(server).await),
+        "enable_instance" => set_enabled(server, params, true),
+        "disable_instance" => set_enabled(server, params, false),
+        "mute_instance" => {
+            let params: MuteParams = serde_json::from_value(params).map_err(ControlError::InvalidParams)?;
+            server.mute(&params.instance, params.seconds.map(Duration::from_secs));
+            Ok(json!({ "instance": params.instance, "muted": true }))
+        }
+        "unmute_instance" => {
+            let params: InstanceParams = serde_json::from_value(params).map_err(ControlError::InvalidParams)?;
+            if server.registry().unmute(&params.instance) {
+                tracing::info!("Unmuted {}", params.instance);
+            }
+            Ok(json!({ "instance": params.instance, "muted": false }))
+        }
+        "kick_session" => {
+            let params: SessionParams = serde_json::from_value(params).map_err(ControlError::InvalidParams)?;
+            match server.registry().kick(params.session) {
+                true => Ok(Value::Null),
+                false => Err(ControlError::NoSuchSession(params.session)),
+            }
+        }
+        "get_profile" => Ok(get_profile(server)),
+        "set_profile" => {
+            let params: ProfileParams = serde_json::from_value(params).map_err(ControlError::InvalidParams)?;
+            match server.set_profile(params.name.as_deref(), Trigger::Manual) {
+                true => Ok(get_profile(server)),
+                false => Err(ControlError::NoSuchProfile(params.name.unwrap_or_default())),
+            }
+        }
+        "clear_cache" => {
+            let params: CacheParams = serde_json::from_value(params).map_err(ControlError::InvalidParams)?;
+            let count = discord::api::cache::clear(params.client_id.as_deref()).await;
+            Ok(json!({ "cleared": count }))
+        }
+        "reload_config" => server.reload().map(|_| Value::Null).map_err(ControlError::Reload),
+        _ => Err(ControlError::MethodNotFound(method.to_owned())),
+    }
+}
+
+fn list_sessions(server: &Server) -> Value {
+    let sessions = server
+        .registry()
+        .list()
+        .into_iter()
+        .map(|(id, session)| json!({
+            "id": id,
+            "client_id": session.client_id,
+            "name": session.name,
+            "pid": session.pid,
+            "instances": session.upstreams,
+            "activity": session.activity,
+            "activity_age": session.activity_at.map(|at| at.elapsed().as_secs()),
+        }))
+        .collect();
+
+    Value::Array(sessions)
+}
+
+async fn list_instances(server: &Server) -> Value {
+    let sessions = server.registry().list();
+    let mut instances = Vec::new();
+
+    for name in server.other_ipc_names() {
+        let connected = sessions.iter().filter(|(_, session)| session.upstreams.contains(&name)).count();
+        let muted_until = server.registry().muted_until(&name);
+        instances.push(json!({
+            "name": name,
+            "alive": discord::ipc::probe(&name).await,
+            "enabled": server.registry().enabled(&name),
+            "muted": muted_until.is_some(),
+            "muted_for": muted_until.flatten().map(|until| until.saturating_duration_since(Instant::now()).as_secs()),
+            "sessions": connected,
+        }));
+    }
+
+    Value::Array(instances)
+}
+
+fn get_profile(server: &Server) -> Value {
+    let (active, trigger) = server.profile();
+    json!({ "active": active, "trigger": trigger.to_string(), "profiles": server.profiles() })
+}
+
+fn set_enabled(server: &Server, params: Value, enabled: bool) -> Result<Value, ControlError> {
+    let params: InstanceParams = serde_json::from_value(params).map_err(ControlError::InvalidParams)?;
+
+    if server.registry().set_enabled(&params.instance, enabled) {
+        tracing::info!("{} {}", if enabled { "Enabled" } else { "Disabled" }, params.instance);
+    }
+
+    Ok(json!({ "instance": params.instance, "enabled": enabled }))
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc;
+    use tokio_util::sync::CancellationToken;
+
+    use super::*;
+    use crate::config::{Config, Source};
+
+    fn server() -> Server {
+        Server::new(CancellationToken::new(), Source::default(), Config::default())
+    }
+
+    async fn request(server: &Server, line: &str) -> Value {
+        respond(server, line).await
+    }
+
+    #[tokio::test]
+    async fn unknown_method() {
+        let response = request(&server(), r#"{"jsonrpc":"2.0","id":1,"method":"explode"}"#).await;
+        assert_eq!(response["id"], 1);
+        assert_eq!(response["error"]["code"], -32601);
+    }
+
+    #[tokio::test]
+    async fn parse_error() {
+        let response = request(&server(), "{").await;
+        assert_eq!(response["error"]["code"], -32700);
+    }
+
+    #[tokio::test]
+    async fn disable_and_enable_instance() {
+        let server = server();
+
+        let response = request(&server, r#"{"id":1,"method":"disable_instance","params":{"instance":"discord-ipc-1"}}"#).await;
+        assert_eq!(response["result"]["enabled"], false);
+        assert!(!server.registry().enabled("discord-ipc-1"));
+
+        request(&server, r#"{"id":2,"method":"enable_instance","params":{"instance":"discord-ipc-1"}}"#).await;
+        assert!(server.registry().enabled("discord-ipc-1"));
+
+        let response = request(&server, r#"{"id":3,"method":"enable_instance","params":{}}"#).await;
+        assert_eq!(response["error"]["code"], -32602);
+    }
+
+    #[tokio::test]
+    async fn mute_and_unmute_instance() {
+        let server = server();
+
+        let response = request(&server, r#"{"id":1,"method":"mute_instance","params":{"instance":"discord-ipc-1","seconds":60}}"#).await;
+        assert_eq!(response["result"]["muted"], true);
+        assert!(matches!(server.registry().muted_until("discord-ipc-1"), Some(Some(_))));
+
+        request(&server, r#"{"id":2,"method":"unmute_instance","params":{"instance":"discord-ipc-1"}}"#).await;
+        assert!(!server.registry().muted("discord-ipc-1"));
+
+        // Too long to represent lasts until unmuted
+        request(&server, r#"{"id":3,"method":"mute_instance","params":{"instance":"discord-ipc-1","seconds":18446744073709551615}}"#).await;
+        assert_eq!(server.registry().muted_until("discord-ipc-1"), Some(None));
+    }
+
+    #[tokio::test]
+    async fn get_and_set_profile() {
+        let config = Config::parse("[profiles.streaming]\nmute = [\"discord-ipc-1\"]").unwrap();
+        let server = Server::new(CancellationToken::new(), Source::default(), config);
+
+        let response = request(&server, r#"{"id":1,"method":"get_profile"}"#).await;
+        assert!(response["result"]["active"].is_null());
+        assert_eq!(response["result"]["profiles"], json!(["streaming"]));
+
+        let response = request(&server, r#"{"id":2,"method":"set_profile","params":{"name":"streaming"}}"#).await;
+        assert_eq!(response["result"]["active"], "streaming");
+        assert_eq!(response["result"]["trigger"], "manual");
+        assert_eq!(server.config().mute, vec!["discord-ipc-1"]);
+
+        let response = request(&server, r#"{"id":3,"method":"set_profile","params":{"name":"work"}}"#).await;
+        assert_eq!(response["error"]["code"], -32000);
+
+        request(&server, r#"{"id":4,"method":"set_profile","params":{"name":null}}"#).await;
+        assert!(server.profile().0.is_none());
+        assert!(server.config().mute.is_empty());
+    }
+
+    #[tokio::test]
+    async fn list_and_kick_sessions() {
+        let server = server();
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let id = server.registry().register(tx, 0).unwrap();
+        server.registry().identify(id, "123");
+
+        let response = request(&server, r#"{"id":1,"method":"list_sessions"}"#).await;
+        assert_eq!(response["result"][0]["id"], id);
+        assert_eq!(response["result"][0]["client_id"], "123");
+
+        let line = json!({ "id": 2, "method": "kick_session", "params": { "session": id } }).to_string();
+        assert!(request(&server, &line).await["result"].is_null());
+
+        let line = json!({ "id": 3, "method": "kick_session", "params": { "session": id + 1 } }).to_string();
+        assert_eq!(request(&server, &line).await["error"]["code"], -32000);
+    }
+}
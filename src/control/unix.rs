@@ -0,0 +1,55 @@
+use std::error::Error;
+
+use tokio::{fs, io::{self, AsyncBufReadExt, AsyncWriteExt, BufReader}, net::UnixStream};
+
+use crate::{control::{NAME, serve}, discord, switch::ipc::Server};
+
+/// Sends a request line to the running switch and reads the response line
+pub async fn request(line: &str) -> io::Result<String> {
+    let stream = UnixStream::connect(discord::ipc::path(NAME)).await?;
+    let (reader, mut writer) = stream.into_split();
+
+    writer.write_all(format!("{}\n", line).as_bytes()).await?;
+
+    let mut response = String::new();
+    BufReader::new(reader).read_line(&mut response).await?;
+    Ok(response)
+}
+
+pub async fn start(server: Server) -> Result<(), Box<dyn Error>> {
+    let path = discord::ipc::path(NAME);
+    let listener = discord::ipc::listen(&path, &server.config().peer).await?;
+    tracing::info!("Control socket listening at {}", path.display());
+
+    loop {
+        let server = server.clone();
+
+        tokio::select! {
+            result = listener.accept() => {
+                match result {
+                    Ok((stream, _)) => {
+                        if !server.trusted(&stream) {
+                            continue;
+                        }
+
+                        tokio::spawn(async move {
+                            let (reader, writer) = stream.into_split();
+                            if let Err(e) = serve(server, reader, writer).await {
+                                tracing::error!("Error handling control client: {}", e);
+                            }
+                        });
+                    }
+                    Err(e) => {
+                        tracing::error!("Error accepting control client: {}", e);
+                    }
+                }
+            }
+
+            _ = server.token().cancelled() => break,
+        }
+    }
+
+    fs::remove_file(&path).await?;
+
+    Ok(())
+}
@@ -0,0 +1,61 @@
+use std::error::Error;
+
+use tokio::{
+    io::{self, AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::windows::named_pipe::{ClientOptions, ServerOptions},
+};
+
+use crate::{control::{NAME, serve}, discord, switch::ipc::Server};
+
+/// Sends a request line to the running switch and reads the response line
+pub async fn request(line: &str) -> io::Result<String> {
+    let pipe = ClientOptions::new().open(discord::ipc::path(NAME))?;
+    let (reader, mut writer) = tokio::io::split(pipe);
+
+    writer.write_all(format!("{}\n", line).as_bytes()).await?;
+
+    let mut response = String::new();
+    BufReader::new(reader).read_line(&mut response).await?;
+    Ok(response)
+}
+
+pub async fn start(server: Server) -> Result<(), Box<dyn Error>> {
+    let path = discord::ipc::path(NAME);
+
+    let mut pipe_server = ServerOptions::new()
+        .first_pipe_instance(true)
+        .create(&path)?;
+
+    tracing::info!("Control pipe listening at {}", path.display());
+
+    loop {
+        let server = server.clone();
+
+        tokio::select! {
+            result = pipe_server.connect() => {
+                match result {
+                    Ok(()) => {
+                        let connected_pipe = pipe_server;
+
+                        // Create a new pipe instance for the next client
+                        pipe_server = ServerOptions::new().create(&path)?;
+
+                        tokio::spawn(async move {
+                            let (reader, writer) = tokio::io::split(connected_pipe);
+                            if let Err(e) = serve(server, reader, writer).await {
+                                tracing::error!("Error handling control client: {}", e);
+                            }
+                        });
+                    }
+                    Err(e) => {
+                        tracing::error!("Error accepting control client: {}", e);
+                    }
+                }
+            }
+
+            _ = server.token().cancelled() => break,
+        }
+    }
+
+    Ok(())
+}
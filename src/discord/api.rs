@@ -1,72 +0,0 @@
-use std::collections::HashMap;
-
-use lazy_static::lazy_static;
-use serde::{Deserialize, Serialize};
-use tokio::sync::RwLock;
-
-const BASE_URL: &str = "https://discord.com/api/v9";
-
-type Data = HashMap<String, ApplicationRpcData>;
-lazy_static! {
-    static ref CACHE: RwLock<Data> = RwLock::new(HashMap::new());
-}
-
-#[derive(Clone, Deserialize)]
-pub struct ApplicationRpcData {
-    pub name: String,
-}
-
-#[derive(Clone, Deserialize, Serialize)]
-pub struct Handshake {
-    pub v: i32,
-    pub client_id: String,
-}
-
-/// Fetches and caches metadata of an RPC application
-pub async fn application_rpc(client_id: &str) -> Result<ApplicationRpcData, reqwest::Error> {
-    // Read cache
-    {
-        let cache = CACHE.read().await;
-        if let Some(data) = cache.get(client_id) {
-            return Ok(data.clone());
-        }
-    }
-
-    let data = reqwest::get(format!(
-        "{}/oauth2/applications/{}/rpc",
-        BASE_URL, client_id
-    ))
-    .await?
-    .json::<ApplicationRpcData>()
-    .await?;
-
-    // Write cache
-    let mut cache = CACHE.write().await;
-    cache.insert(client_id.to_string(), data.clone());
-
-    Ok(data)
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn handshake_roundtrip() {
-        let handshake = Handshake {
-            v: 1,
-            client_id: String::from("123456789"),
-        };
-        let json = serde_json::to_string(&handshake).unwrap();
-        let parsed: Handshake = serde_json::from_str(&json).unwrap();
-        assert_eq!(parsed.v, 1);
-        assert_eq!(parsed.client_id, "123456789");
-    }
-
-    #[test]
-    fn application_rpc_data_deserialize() {
-        let json = r#"{"name":"My App"}"#;
-        let data: ApplicationRpcData = serde_json::from_str(json).unwrap();
-        assert_eq!(data.name, "My App");
-    }
-}
@@ -0,0 +1,330 @@
+use std::{collections::HashMap, fs, future::Future, io, path::{Path, PathBuf}, time::{Duration, SystemTime, UNIX_EPOCH}};
+
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use tokio::sync::{Mutex, OnceCell};
+use tokio_util::sync::CancellationToken;
+
+use crate::{config, discord::api::{ApplicationRpcData, Asset, error::ApiError}};
+
+const FILE_NAME: &str = "applications.json";
+
+/// How often entries that were only used, not added, are written out
+const FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The daemon's copy of the store, read from the cache file on first use
+static CACHE: OnceCell<Mutex<Cache>> = OnceCell::const_new();
+
+/// Serializes writes of the cache file, so an older copy never replaces a newer one
+static FLUSH: Mutex<()> = Mutex::const_new(());
+
+#[derive(Debug, Default)]
+struct Cache {
+    store: Store,
+    /// Whether the store changed since it was last written
+    dirty: bool,
+}
+
+/// Application metadata kept between runs
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Store {
+    #[serde(default)]
+    pub applications: HashMap<String, Entry<ApplicationRpcData>>,
+    #[serde(default)]
+    pub assets: HashMap<String, Entry<Vec<Asset>>>,
+}
+
+/// A fetched value, or `None` if Discord didn't know the application
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Entry<T> {
+    pub value: Option<T>,
+    /// Unix time it was fetched
+    pub fetched: u64,
+    /// Unix time it was last used, for evicting the least recently used
+    pub used: u64,
+}
+
+impl<T> Entry<T> {
+    /// Whether the entry can be used instead of fetching it again
+    fn fresh(&self, settings: &config::Cache, now: u64) -> bool {
+        let ttl = match self.value {
+            Some(_) => settings.ttl,
+            None => settings.negative_ttl,
+        };
+
+        now.saturating_sub(self.fetched) < ttl
+    }
+}
+
+/// Which of the store's tables a value lives in
+pub type Table<T> = fn(&mut Store) -> &mut HashMap<String, Entry<T>>;
+
+impl Store {
+    /// Reads the store, starting over if it is missing or unreadable
+    pub fn load(path: &Path) -> Store {
+        let contents = match fs::read(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Store::default(),
+            Err(e) => {
+                tracing::warn!("Unable to read cache {}: {}", path.display(), e);
+                return Store::default();
+            }
+        };
+
+        serde_json::from_slice(&contents).unwrap_or_else(|e| {
+            tracing::warn!("Discarding invalid cache {}: {}", path.display(), e);
+            Store::default()
+        })
+    }
+
+    /// Writes the store through a temporary file, so readers never see a partial one
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let temporary = path.with_extension("json.tmp");
+        fs::write(&temporary, serde_json::to_vec(self)?)?;
+        fs::rename(&temporary, path)
+    }
+
+    /// Adds an entry, evicting the least recently used beyond `capacity`
+    fn insert<T>(&mut self, table: Table<T>, client_id: &str, entry: Entry<T>, capacity: usize) {
+        let table = table(self);
+        table.insert(client_id.to_owned(), entry);
+
+        while table.len() > capacity {
+            let Some(oldest) = table.iter().min_by_key(|(_, entry)| entry.used).map(|(id, _)| id.clone()) else {
+                break;
+            };
+            table.remove(&oldest);
+        }
+    }
+
+    /// Applications with anything cached, sorted
+    pub fn client_ids(&self) -> Vec<String> {
+        let mut ids = self.applications.keys().chain(self.assets.keys()).cloned().collect::<Vec<_>>();
+        ids.sort();
+        ids.dedup();
+        ids
+    }
+
+    /// Forgets one application, or all of them, returning how many were forgotten
+    pub fn clear(&mut self, client_id: Option<&str>) -> usize {
+        let count = match client_id {
+            Some(client_id) => self.client_ids().iter().filter(|id| *id == client_id).count(),
+            None => self.client_ids().len(),
+        };
+
+        match client_id {
+            Some(client_id) => {
+                self.applications.remove(client_id);
+                self.assets.remove(client_id);
+            }
+            None => *self = Store::default(),
+        }
+
+        count
+    }
+}
+
+/// Location of the cache file
+pub fn path() -> Option<PathBuf> {
+    dir().map(|dir| dir.join(FILE_NAME))
+}
+
+/// Directory holding presence-switch's cache
+pub fn dir() -> Option<PathBuf> {
+    #[cfg(unix)]
+    let base = std::env::var_os("XDG_CACHE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")));
+
+    #[cfg(windows)]
+    let base = std::env::var_os("LOCALAPPDATA").map(PathBuf::from);
+
+    base.map(|dir| dir.join(env!("CARGO_PKG_NAME")))
+}
+
+async fn cache() -> &'static Mutex<Cache> {
+    CACHE
+        .get_or_init(|| async {
+            let store = tokio::task::spawn_blocking(|| path().map(|path| Store::load(&path)).unwrap_or_default())
+                .await
+                .unwrap_or_default();
+            Mutex::new(Cache { store, dirty: false })
+        })
+        .await
+}
+
+/// Returns an application's value from the cache, fetching it when missing or expired. `fetch`
+/// gives `None` if Discord doesn't know the application, which is remembered too. If fetching
+/// fails, an expired value is used rather than none.
+pub async fn get<T, F>(settings: &config::Cache, table: Table<T>, client_id: &str, fetch: F) -> Result<T, ApiError>
+where
+    T: Clone + Serialize + DeserializeOwned,
+    F: Future<Output = Result<Option<T>, ApiError>>,
+{
+    let now = now();
+
+    let stale = {
+        let mut cache = cache().await.lock().await;
+
+        let entry = table(&mut cache.store).get_mut(client_id);
+        match entry {
+            Some(entry) if entry.fresh(settings, now) => {
+                // Only written out with the next addition or flush
+                entry.used = now;
+                let value = entry.value.clone();
+                cache.dirty = true;
+                return value.ok_or_else(|| ApiError::UnknownApplication(client_id.to_owned()));
+            }
+            Some(entry) => entry.value.clone(),
+            None => None,
+        }
+    };
+
+    let value = match fetch.await {
+        Ok(value) => value,
+        Err(e) => {
+            return match stale {
+                Some(value) => {
+                    match e {
+                        ApiError::Offline => tracing::debug!("Using expired metadata for {} while offline", client_id),
+                        e => tracing::warn!("Using expired metadata for {}: {}", client_id, e),
+                    }
+                    Ok(value)
+                }
+                None => Err(e),
+            };
+        }
+    };
+
+    {
+        let mut cache = cache().await.lock().await;
+        let entry = Entry {
+            value: value.clone(),
+            fetched: now,
+            used: now,
+        };
+        cache.store.insert(table, client_id, entry, settings.capacity);
+        cache.dirty = true;
+    }
+    flush().await;
+
+    value.ok_or_else(|| ApiError::UnknownApplication(client_id.to_owned()))
+}
+
+/// Forgets one application, or all of them, returning how many were forgotten
+pub async fn clear(client_id: Option<&str>) -> usize {
+    let count = {
+        let mut cache = cache().await.lock().await;
+        cache.dirty = true;
+        cache.store.clear(client_id)
+    };
+    flush().await;
+
+    count
+}
+
+/// Writes the store out if it changed, off the async runtime
+pub async fn flush() {
+    let _flush = FLUSH.lock().await;
+    let Some(cache) = CACHE.get() else {
+        return;
+    };
+    let Some(path) = path() else {
+        return;
+    };
+
+    let store = {
+        let mut cache = cache.lock().await;
+        if !cache.dirty {
+            return;
+        }
+        cache.dirty = false;
+        cache.store.clone()
+    };
+
+    let result = tokio::task::spawn_blocking(move || store.save(&path).map_err(|e| (path, e))).await;
+    if let Ok(Err((path, e))) = result {
+        tracing::warn!("Unable to write cache {}: {}", path.display(), e);
+    }
+}
+
+/// Periodically writes out when entries were last used, and once more when the server is cancelled
+pub async fn watch(token: CancellationToken) {
+    loop {
+        tokio::select! {
+            _ = token.cancelled() => break,
+            _ = tokio::time::sleep(FLUSH_INTERVAL) => flush().await,
+        }
+    }
+
+    flush().await;
+}
+
+/// Current Unix time, which entries are stamped with
+pub fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|duration| duration.as_secs()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(name: Option<&str>, fetched: u64, used: u64) -> Entry<ApplicationRpcData> {
+        Entry {
+            value: name.map(|name| ApplicationRpcData { name: name.to_owned() }),
+            fetched,
+            used,
+        }
+    }
+
+    #[test]
+    fn fresh_entries() {
+        let settings = config::Cache {
+            ttl: 100,
+            negative_ttl: 10,
+            capacity: 8,
+        };
+
+        assert!(entry(Some("App"), 1000, 1000).fresh(&settings, 1099));
+        assert!(!entry(Some("App"), 1000, 1000).fresh(&settings, 1100));
+        assert!(entry(None, 1000, 1000).fresh(&settings, 1009));
+        assert!(!entry(None, 1000, 1000).fresh(&settings, 1010));
+    }
+
+    #[test]
+    fn evict_least_recently_used() {
+        let mut store = Store::default();
+        store.insert(|store| &mut store.applications, "1", entry(Some("One"), 0, 30), 2);
+        store.insert(|store| &mut store.applications, "2", entry(Some("Two"), 0, 10), 2);
+        store.insert(|store| &mut store.applications, "3", entry(None, 0, 20), 2);
+
+        assert_eq!(store.client_ids(), vec!["1", "3"]);
+    }
+
+    #[test]
+    fn save_and_clear() {
+        let dir = std::env::temp_dir().join(format!("presence-switch-cache-{}", std::process::id()));
+        let path = dir.join(FILE_NAME);
+
+        let mut store = Store::default();
+        store.insert(|store| &mut store.applications, "1", entry(Some("One"), 0, 0), 8);
+        store.insert(|store| &mut store.applications, "2", entry(Some("Two"), 0, 0), 8);
+        store.assets.insert(String::from("2"), Entry { value: Some(Vec::new()), fetched: 0, used: 0 });
+        store.save(&path).unwrap();
+
+        let mut store = Store::load(&path);
+        assert_eq!(store.applications["1"].value.as_ref().unwrap().name, "One");
+        assert_eq!(store.clear(Some("2")), 1);
+        assert_eq!(store.client_ids(), vec!["1"]);
+        assert_eq!(store.clear(None), 1);
+
+        fs::write(&path, "{").unwrap();
+        assert!(Store::load(&path).applications.is_empty());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
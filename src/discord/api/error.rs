@@ -0,0 +1,45 @@
+use std::fmt;
+
+#[derive(Debug)]
+pub enum ApiError {
+    #[cfg(feature = "network")]
+    Request(reqwest::Error),
+    /// Discord doesn't know the application, possibly remembered from an earlier lookup
+    UnknownApplication(String),
+    /// Discord didn't answer within `api.timeout`
+    Timeout,
+    /// Network access is disabled by `api.offline` or the build
+    Offline,
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            #[cfg(feature = "network")]
+            ApiError::Request(e) => write!(f, "{}", e),
+            ApiError::UnknownApplication(client_id) => write!(f, "unknown application {}", client_id),
+            ApiError::Timeout => write!(f, "request timed out"),
+            ApiError::Offline => write!(f, "network access is disabled"),
+        }
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+#[cfg(feature = "network")]
+impl From<reqwest::Error> for ApiError {
+    fn from(value: reqwest::Error) -> Self {
+        ApiError::Request(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn api_error_display() {
+        let err = ApiError::UnknownApplication(String::from("123"));
+        assert_eq!(format!("{}", err), "unknown application 123");
+    }
+}
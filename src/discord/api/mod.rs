@@ -0,0 +1,181 @@
+use std::{future::Future, time::Duration};
+
+#[cfg(feature = "network")]
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+
+use crate::{config::{self, Config}, discord::api::error::ApiError};
+
+pub mod cache;
+pub mod error;
+
+/// Wait after the first failed attempt of a lookup, doubled after each further one
+const BACKOFF: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ApplicationRpcData {
+    pub name: String,
+}
+
+/// An image uploaded to an application's rich presence art assets
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Asset {
+    /// Key activities refer to the image by
+    pub name: String,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Handshake {
+    pub v: i32,
+    pub client_id: String,
+}
+
+/// Fetches and caches metadata of an RPC application
+pub async fn application_rpc(config: &Config, client_id: &str) -> Result<ApplicationRpcData, ApiError> {
+    let url = format!("{}/oauth2/applications/{}/rpc", config.api.base_url, client_id);
+    cache::get(&config.cache, |store| &mut store.applications, client_id, fetch(&config.api, url)).await
+}
+
+/// Fetches and caches the art assets of an RPC application
+pub async fn application_assets(config: &Config, client_id: &str) -> Result<Vec<Asset>, ApiError> {
+    let url = format!("{}/oauth2/applications/{}/assets", config.api.base_url, client_id);
+    cache::get(&config.cache, |store| &mut store.assets, client_id, fetch(&config.api, url)).await
+}
+
+/// Whether the API must not be contacted, by config or because the build lacks the `network`
+/// feature
+pub fn offline(api: &config::Api) -> bool {
+    api.offline || !cfg!(feature = "network")
+}
+
+/// Runs a lookup until it succeeds, Discord doesn't know the application or the API is offline, giving each attempt
+/// `api.timeout` and backing off between them
+pub async fn retry<T, F, Fut>(api: &config::Api, mut lookup: F) -> Result<T, ApiError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, ApiError>>,
+{
+    let timeout = Duration::from_secs(api.timeout);
+    let mut backoff = BACKOFF;
+    let mut attempt = 1;
+
+    loop {
+        let result = tokio::time::timeout(timeout, lookup()).await.unwrap_or(Err(ApiError::Timeout));
+        match result {
+            Err(ApiError::UnknownApplication(_) | ApiError::Offline) => return result,
+            Err(e) if attempt < api.attempts => {
+                tracing::debug!("Lookup attempt {} failed, retrying in {:?}: {}", attempt, backoff, e);
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Fetches JSON from the API, or `None` if the application doesn't exist
+async fn fetch<T: DeserializeOwned>(api: &config::Api, url: String) -> Result<Option<T>, ApiError> {
+    if offline(api) {
+        return Err(ApiError::Offline);
+    }
+
+    request(url).await
+}
+
+#[cfg(feature = "network")]
+async fn request<T: DeserializeOwned>(url: String) -> Result<Option<T>, ApiError> {
+    let response = reqwest::get(url).await?;
+    if matches!(response.status(), StatusCode::NOT_FOUND | StatusCode::BAD_REQUEST) {
+        return Ok(None);
+    }
+
+    Ok(Some(response.error_for_status()?.json::<T>().await?))
+}
+
+#[cfg(not(feature = "network"))]
+async fn request<T: DeserializeOwned>(_url: String) -> Result<Option<T>, ApiError> {
+    Err(ApiError::Offline)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn handshake_roundtrip() {
+        let handshake = Handshake {
+            v: 1,
+            client_id: String::from("123456789"),
+        };
+        let json = serde_json::to_string(&handshake).unwrap();
+        let parsed: Handshake = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.v, 1);
+        assert_eq!(parsed.client_id, "123456789");
+    }
+
+    #[test]
+    fn application_rpc_data_deserialize() {
+        let json = r#"{"name":"My App"}"#;
+        let data: ApplicationRpcData = serde_json::from_str(json).unwrap();
+        assert_eq!(data.name, "My App");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retry_with_backoff() {
+        let api = config::Api {
+            timeout: 1,
+            attempts: 3,
+            ..config::Api::default()
+        };
+
+        let mut attempts = 0;
+        let result = retry(&api, || {
+            attempts += 1;
+            let attempt = attempts;
+            async move {
+                match attempt {
+                    1 => Err(ApiError::Timeout),
+                    // Never answers, so times out
+                    2 => std::future::pending().await,
+                    _ => Ok(attempt),
+                }
+            }
+        })
+        .await;
+        assert_eq!(result.unwrap(), 3);
+
+        let mut attempts = 0;
+        let result: Result<(), _> = retry(&api, || {
+            attempts += 1;
+            async { Err(ApiError::UnknownApplication(String::from("1"))) }
+        })
+        .await;
+        assert!(matches!(result, Err(ApiError::UnknownApplication(_))));
+        assert_eq!(attempts, 1);
+
+        let result: Result<(), _> = retry(&api, || async { Err(ApiError::Timeout) }).await;
+        assert!(matches!(result, Err(ApiError::Timeout)));
+    }
+
+    #[tokio::test]
+    async fn offline_fetch() {
+        let api = config::Api {
+            offline: true,
+            // Nothing listens here, so a request would fail differently
+            base_url: String::from("http://127.0.0.1:9"),
+            ..config::Api::default()
+        };
+
+        let result = fetch::<ApplicationRpcData>(&api, format!("{}/oauth2/applications/1/rpc", api.base_url)).await;
+        assert!(matches!(result, Err(ApiError::Offline)));
+        assert!(offline(&api));
+    }
+
+    #[test]
+    fn assets_deserialize() {
+        let json = r#"[{"id":"1","type":1,"name":"logo"},{"id":"2","type":1,"name":"map_dust2"}]"#;
+        let assets: Vec<Asset> = serde_json::from_str(json).unwrap();
+        assert_eq!(assets[1].name, "map_dust2");
+    }
+}
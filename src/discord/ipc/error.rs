@@ -4,6 +4,9 @@ use std::fmt;
 pub enum IpcError {
     InvalidOpCode,
     NoNameAvailable,
+    NotConnected,
+    QueueFull,
+    FrameTooLarge(usize),
 }
 
 impl fmt::Display for IpcError {
@@ -11,6 +14,9 @@ impl fmt::Display for IpcError {
         match self {
             IpcError::InvalidOpCode => write!(f, "invalid opcode"),
             IpcError::NoNameAvailable => write!(f, "no name available"),
+            IpcError::NotConnected => write!(f, "not connected"),
+            IpcError::QueueFull => write!(f, "queue full"),
+            IpcError::FrameTooLarge(length) => write!(f, "frame of {} bytes is too large", length),
         }
     }
 }
@@ -21,6 +27,9 @@ impl From<IpcError> for std::io::Error {
         match value {
             IpcError::InvalidOpCode => std::io::Error::other(value),
             IpcError::NoNameAvailable => std::io::Error::new(std::io::ErrorKind::NotFound, value),
+            IpcError::NotConnected => std::io::Error::new(std::io::ErrorKind::NotConnected, value),
+            IpcError::QueueFull => std::io::Error::new(std::io::ErrorKind::WouldBlock, value),
+            IpcError::FrameTooLarge(_) => std::io::Error::new(std::io::ErrorKind::InvalidData, value),
         }
     }
 }
@@ -33,6 +42,9 @@ mod tests {
     fn ipc_error_display() {
         assert_eq!(format!("{}", IpcError::InvalidOpCode), "invalid opcode");
         assert_eq!(format!("{}", IpcError::NoNameAvailable), "no name available");
+        assert_eq!(format!("{}", IpcError::NotConnected), "not connected");
+        assert_eq!(format!("{}", IpcError::QueueFull), "queue full");
+        assert_eq!(format!("{}", IpcError::FrameTooLarge(10)), "frame of 10 bytes is too large");
     }
 
     #[test]
@@ -42,5 +54,11 @@ mod tests {
 
         let io_err: std::io::Error = IpcError::NoNameAvailable.into();
         assert_eq!(io_err.kind(), std::io::ErrorKind::NotFound);
+
+        let io_err: std::io::Error = IpcError::NotConnected.into();
+        assert_eq!(io_err.kind(), std::io::ErrorKind::NotConnected);
+
+        let io_err: std::io::Error = IpcError::FrameTooLarge(10).into();
+        assert_eq!(io_err.kind(), std::io::ErrorKind::InvalidData);
     }
 }
@@ -1,16 +1,19 @@
 use std::{error::Error, fmt, path::PathBuf};
 
 use serde::Deserialize;
-use tokio::sync::{broadcast, mpsc};
+use tokio::{io::{self, AsyncRead, AsyncReadExt}, sync::mpsc};
 use tokio_util::bytes::{BufMut, BytesMut};
 
 use crate::discord::ipc::error::IpcError;
 
-mod error;
+pub mod error;
 
 #[cfg(unix)]
 mod unix;
 
+#[cfg(unix)]
+pub use unix::listen;
+
 #[cfg(windows)]
 mod windows;
 
@@ -68,39 +71,87 @@ impl fmt::Display for OpCode {
     }
 }
 
+/// Largest frame accepted from a Discord instance
+const MAX_FRAME_LENGTH: usize = 16 * 1024 * 1024;
+
 pub struct Client {
     pub name: String,
-    channel: (broadcast::Sender<Data>, broadcast::Receiver<Data>),
-    switch_tx: mpsc::UnboundedSender<Data>
+    tx: Option<mpsc::Sender<Data>>,
+    /// Receives every frame read from this instance, tagged with its name
+    switch_tx: mpsc::UnboundedSender<(String, Data)>
 }
 
 impl Client {
-    pub fn new(name: &str, channel: &(broadcast::Sender<Data>, broadcast::Receiver<Data>), switch_tx: mpsc::UnboundedSender<Data>) -> Client {
-        let (tx, rx) = channel;
-
+    pub fn new(name: &str, switch_tx: mpsc::UnboundedSender<(String, Data)>) -> Client {
         Client {
             name: name.to_owned(),
-            channel: (tx.clone(), rx.resubscribe()),
+            tx: None,
             switch_tx,
         }
     }
 
-    pub async fn connect(&self) -> Result<(), Box<dyn Error>> {
+    /// Connects to this Discord instance, queueing up to `capacity` messages before new ones are
+    /// dropped
+    pub async fn connect(&mut self, capacity: usize) -> Result<(), Box<dyn Error>> {
         tracing::debug!("Connecting to {}", self.name);
 
+        let (tx, rx) = mpsc::channel::<Data>(capacity);
+
         #[cfg(unix)]
-        unix::connect(self).await?;
+        unix::connect(self, rx).await?;
 
         #[cfg(windows)]
-        windows::connect(self).await?;
+        windows::connect(self, rx).await?;
+
+        self.tx = Some(tx);
 
         tracing::debug!("Connected to {}", self.name);
 
         Ok(())
     }
+
+    /// Queues a message to be written to this Discord instance
+    pub fn send(&self, data: Data) -> Result<(), IpcError> {
+        let Some(tx) = self.tx.as_ref() else {
+            return Err(IpcError::NotConnected);
+        };
+
+        tx.try_send(data).map_err(|e| match e {
+            mpsc::error::TrySendError::Full(_) => IpcError::QueueFull,
+            mpsc::error::TrySendError::Closed(_) => IpcError::NotConnected,
+        })
+    }
+}
+
+/// Reads a frame, returning `None` once the other end has disconnected
+///
+/// Frames longer than `max_length` fail with `IpcError::FrameTooLarge` before anything is allocated.
+pub async fn read<R: AsyncRead + Unpin>(reader: &mut R, max_length: usize) -> io::Result<Option<Data>> {
+    // Expect a u32 indicating opcode
+    let opcode = match reader.read_u32_le().await {
+        Ok(opcode) => OpCode::from_u32(opcode)?,
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    };
+
+    // Expect a u32 indicating length
+    let length = reader.read_u32_le().await? as usize;
+    if length > max_length {
+        return Err(IpcError::FrameTooLarge(length).into());
+    }
+
+    // Read the message
+    let mut buf = vec![0; length];
+    reader.read_exact(&mut buf).await?;
+
+    let msg = String::from_utf8(buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+    Ok(Some(Data { opcode, msg }))
 }
 
-pub fn names() -> Vec<String> {
+/// Names of the Discord sockets found in the runtime directory and `extra_dirs`. Sockets in
+/// `extra_dirs` are named by their full path, as their file names repeat across directories.
+pub fn names(extra_dirs: &[PathBuf]) -> Vec<String> {
     let dir = dir();
     let mut pipes = Vec::new();
 
@@ -113,9 +164,41 @@ pub fn names() -> Vec<String> {
         }
     }
 
+    for dir in extra_dirs {
+        for i in 0..10 {
+            let path = dir.join(format!("discord-ipc-{}", i));
+            if path.as_path().exists() {
+                pipes.push(path.display().to_string());
+            }
+        }
+    }
+
     pipes
 }
 
+/// Whether something is listening on a socket, as opposed to it being left behind
+pub async fn probe(name: &str) -> bool {
+    #[cfg(unix)]
+    return unix::probe(&path(name)).await;
+
+    #[cfg(windows)]
+    return windows::probe(&path(name));
+}
+
+/// Whether a socket was left behind by a process that exited without cleaning up, as opposed to
+/// being served or not being a socket at all
+pub async fn stale(name: &str) -> bool {
+    #[cfg(unix)]
+    return unix::stale(&path(name)).await;
+
+    // Named pipes disappear along with the process serving them
+    #[cfg(windows)]
+    {
+        let _ = name;
+        false
+    }
+}
+
 pub fn next_name() -> Result<String, Box<dyn Error>> {
     let dir = dir();
 
@@ -146,6 +229,7 @@ pub fn dir() -> PathBuf {
     PathBuf::from(r"\\.\pipe")
 }
 
+/// Path of a socket, where `name` is either a name in the runtime directory or a full path
 pub fn path(name: &str) -> PathBuf {
     let mut dir = dir();
     dir.push(name);
@@ -240,10 +324,127 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[tokio::test]
+    async fn read_frame() {
+        let data = Data {
+            opcode: OpCode::Frame,
+            msg: String::from("test"),
+        };
+        let buf = data.to_buf();
+        let mut reader = &buf[..];
+
+        let data = read(&mut reader, MAX_FRAME_LENGTH).await.unwrap().unwrap();
+        assert!(matches!(data.opcode, OpCode::Frame));
+        assert_eq!(data.msg, "test");
+
+        // Nothing left to read
+        assert!(read(&mut reader, MAX_FRAME_LENGTH).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn read_truncated_frame() {
+        let data = Data {
+            opcode: OpCode::Frame,
+            msg: String::from("test"),
+        };
+        let buf = data.to_buf();
+        let mut reader = &buf[..buf.len() - 1];
+
+        let err = read(&mut reader, MAX_FRAME_LENGTH).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[tokio::test]
+    async fn read_frame_too_large() {
+        let mut buf = BytesMut::new();
+        buf.put_u32_le(1);
+        buf.put_u32_le(u32::MAX);
+        let mut reader = &buf[..];
+
+        let err = read(&mut reader, MAX_FRAME_LENGTH).await.unwrap_err();
+        let err = err.get_ref().and_then(|e| e.downcast_ref::<IpcError>());
+        assert!(matches!(err, Some(IpcError::FrameTooLarge(length)) if *length == u32::MAX as usize));
+    }
+
+    #[tokio::test]
+    async fn read_invalid_opcode() {
+        let mut buf = BytesMut::new();
+        buf.put_u32_le(9);
+        buf.put_u32_le(0);
+        let mut reader = &buf[..];
+
+        assert!(read(&mut reader, MAX_FRAME_LENGTH).await.is_err());
+    }
+
     #[test]
     fn path_appends_name() {
         let name = String::from("discord-ipc-0");
         let result = path(&name);
         assert!(result.ends_with("discord-ipc-0"));
     }
+
+    #[cfg(unix)]
+    #[test]
+    fn path_keeps_full_path() {
+        let name = "/run/user/1000/app/com.discordapp.Discord/discord-ipc-0";
+        assert_eq!(path(name), PathBuf::from(name));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn listen_modes() {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+        let dir = std::env::temp_dir().join(format!("presence-switch-listen-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("discord-ipc-0");
+        let mode = |path: &std::path::Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+
+        let listener = listen(&path, &crate::config::Peer::default()).await.unwrap();
+        assert_eq!(mode(&path), 0o600);
+        // A served socket isn't replaced
+        assert!(listen(&path, &crate::config::Peer::default()).await.is_err());
+        drop(listener);
+
+        // SAFETY: getgid is always successful
+        let gid = unsafe { libc::getgid() };
+        let peer = crate::config::Peer { uids: Vec::new(), gids: vec![gid] };
+        let listener = listen(&path, &peer).await.unwrap();
+        assert_eq!(mode(&path), 0o660);
+        assert_eq!(std::fs::metadata(&path).unwrap().gid(), gid);
+        drop(listener);
+
+        let peer = crate::config::Peer { uids: vec![65534], gids: Vec::new() };
+        let _listener = listen(&path, &peer).await.unwrap();
+        assert_eq!(mode(&path), 0o666);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn stale_sockets() {
+        let dir = std::env::temp_dir().join(format!("presence-switch-stale-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("discord-ipc-0");
+        assert!(!unix::stale(&path).await);
+
+        let listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        assert!(!unix::stale(&path).await);
+
+        // Dropping the listener leaves the socket behind, as a killed process would
+        drop(listener);
+        assert!(unix::stale(&path).await);
+        let _listener = listen(&path, &crate::config::Peer::default()).await.unwrap();
+        assert!(!unix::stale(&path).await);
+
+        // Files that aren't sockets are never replaced
+        let file = dir.join("discord-ipc-1");
+        std::fs::write(&file, "").unwrap();
+        assert!(!unix::stale(&file).await);
+        assert!(listen(&file, &crate::config::Peer::default()).await.is_err());
+        assert!(file.exists());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
 }
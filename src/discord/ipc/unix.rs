@@ -1,29 +1,27 @@
-use std::error::Error;
+use std::{error::Error, fs::{self, Permissions}, os::unix::fs::{FileTypeExt, PermissionsExt}, path::Path};
 
 use tokio::{
-    io::{self, AsyncReadExt, AsyncWriteExt},
-    net::UnixStream,
+    io::{self, AsyncWriteExt},
+    net::{UnixListener, UnixSocket, UnixStream},
+    sync::mpsc,
 };
-use tokio_util::bytes::BytesMut;
 
-use crate::discord::{self, ipc::{Client, Data, OpCode}};
+use crate::{config, discord::{self, ipc::{Client, Data}}};
 
-pub async fn connect(client: &Client) -> Result<(), Box<dyn Error>> {
+pub async fn connect(client: &Client, mut rx: mpsc::Receiver<Data>) -> Result<(), Box<dyn Error>> {
     let path = discord::ipc::path(&client.name);
     let stream = UnixStream::connect(path).await?;
 
     let (mut read_half, mut write_half) = stream.into_split();
-    let mut discord_rx = client.channel.1.resubscribe();
 
     // Writer: Switch -> Discord IPC
     let name = client.name.clone();
     tokio::spawn(async move {
         loop {
-            let data = match discord_rx.recv().await {
-                Ok(data) => data,
-                Err(e) => {
+            let data = match rx.recv().await {
+                Some(data) => data,
+                None => {
                     // Channel is closed, closing writer
-                    tracing::error!("Error reading from Discord broadcast channel: {}", e);
                     break;
                 }
             };
@@ -44,44 +42,86 @@ pub async fn connect(client: &Client) -> Result<(), Box<dyn Error>> {
     let switch_tx = client.switch_tx.clone();
     tokio::spawn(async move {
         loop {
-            read_half.readable().await?;
-
-            let opcode = OpCode::from_u32(read_half.read_u32_le().await?)?;
-
-            // Expect a u32 indicating length
-            let length = read_half.read_u32_le().await? as usize;
+            let data = match discord::ipc::read(&mut read_half, discord::ipc::MAX_FRAME_LENGTH).await {
+                Ok(Some(data)) => data,
+                Ok(None) => {
+                    // Client disconnected
+                    tracing::info!("{} disconnected", name);
+                    break;
+                }
+                Err(e) => {
+                    tracing::error!("Could not read from {}: {}", name, e);
+                    break;
+                }
+            };
 
-            // Read the message
-            let mut buf = BytesMut::with_capacity(length);
-            let n = read_half.read_buf(&mut buf).await?;
+            tracing::trace!("Discord IPC Read: {} {} {} {}", name, data.opcode, data.len(), data.msg);
 
-            if n == 0 {
-                // Client disconnected
-                tracing::info!("{} disconnected", name);
+            if switch_tx.send((name.clone(), data)).is_err() {
+                // Switch client is gone, closing reader
                 break;
             }
+        }
+    });
+
+    Ok(())
+}
 
-            match String::from_utf8(buf.to_vec()) {
-                Ok(msg) => {
-                    tracing::trace!("Discord IPC Read: {} {} {} {}", name, opcode, length, msg);
+pub async fn probe(path: &Path) -> bool {
+    UnixStream::connect(path).await.is_ok()
+}
 
-                    let data = Data {
-                        opcode,
-                        msg
-                    };
+/// Whether the path is a socket nothing listens on any more. Other connection errors, such as a
+/// full backlog or a lack of permission, mean it is still in use.
+pub async fn stale(path: &Path) -> bool {
+    fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket())
+        && UnixStream::connect(path).await.is_err_and(|e| e.kind() == io::ErrorKind::ConnectionRefused)
+}
 
-                    if let Err(e) = switch_tx.send(data) {
-                        tracing::error!("Could not send to mpsc channel: {}", e);
-                    }
-                },
-                Err(e) => tracing::error!("Could not decode message to UTF-8 string: {}", e),
+/// Connections each socket queues before they are accepted, as in `std::os::unix::net::UnixListener`
+const BACKLOG: u32 = 128;
+
+/// Listens on a socket only our own user can connect to, opened up to the users and groups `peer`
+/// allows. A socket left behind at the path is replaced, but not one that is still served.
+pub async fn listen(path: &Path, peer: &config::Peer) -> Result<UnixListener, Box<dyn Error>> {
+    let socket = loop {
+        let socket = UnixSocket::new_stream()?;
+        match socket.bind(path) {
+            Ok(()) => break socket,
+            Err(e) if e.kind() == io::ErrorKind::AddrInUse => {
+                // Only replace a socket left behind by a process that exited without cleaning up
+                if !stale(path).await {
+                    return Err(Box::new(e));
+                }
+                tracing::info!("Replacing stale socket {}", path.display());
+                fs::remove_file(path)?;
             }
+            Err(e) => return Err(Box::new(e)),
+        }
+    };
 
+    // Connections are refused until the socket listens, so its mode is settled first
+    share(path, peer)?;
 
-        }
+    Ok(socket.listen(BACKLOG)?)
+}
 
-        Ok::<_, io::Error>(())
-    });
+/// Sets the socket's mode, widened for other peers and leaving `SO_PEERCRED` checks to decide who
+/// is served
+fn share(path: &Path, peer: &config::Peer) -> io::Result<()> {
+    let mode = match (peer.uids.as_slice(), peer.gids.as_slice()) {
+        ([], []) => 0o600,
+        // A single group can be given the socket, provided we are a member. Otherwise the
+        // socket stays private rather than being opened up to everyone.
+        ([], [gid]) => match std::os::unix::fs::chown(path, None, Some(*gid)) {
+            Ok(()) => 0o660,
+            Err(e) => {
+                tracing::warn!("Unable to give {} to group {}, keeping it private: {}", path.display(), gid, e);
+                0o600
+            }
+        },
+        _ => 0o666,
+    };
 
-    Ok(())
+    fs::set_permissions(path, Permissions::from_mode(mode))
 }
@@ -1,29 +1,27 @@
-use std::error::Error;
+use std::{error::Error, path::Path};
 
 use tokio::{
-    io::{self, AsyncReadExt, AsyncWriteExt},
+    io::{self, AsyncWriteExt},
     net::windows::named_pipe::ClientOptions,
+    sync::mpsc,
 };
-use tokio_util::bytes::BytesMut;
 
-use crate::discord::{self, ipc::{Client, Data, OpCode}};
+use crate::discord::{self, ipc::{Client, Data}};
 
-pub async fn connect(client: &Client) -> Result<(), Box<dyn Error>> {
+pub async fn connect(client: &Client, mut rx: mpsc::Receiver<Data>) -> Result<(), Box<dyn Error>> {
     let path = discord::ipc::path(&client.name);
     let pipe = ClientOptions::new().open(&path)?;
 
     let (mut read_half, mut write_half) = tokio::io::split(pipe);
-    let mut discord_rx = client.channel.1.resubscribe();
 
     // Writer: Switch -> Discord IPC
     let name = client.name.clone();
     tokio::spawn(async move {
         loop {
-            let data = match discord_rx.recv().await {
-                Ok(data) => data,
-                Err(e) => {
+            let data = match rx.recv().await {
+                Some(data) => data,
+                None => {
                     // Channel is closed, closing writer
-                    tracing::error!("Error reading from Discord broadcast channel: {}", e);
                     break;
                 }
             };
@@ -42,41 +40,38 @@ pub async fn connect(client: &Client) -> Result<(), Box<dyn Error>> {
     let switch_tx = client.switch_tx.clone();
     tokio::spawn(async move {
         loop {
-            // Expect a u32 indicating opcode
-            let opcode = OpCode::from_u32(read_half.read_u32_le().await?)?;
-
-            // Expect a u32 indicating length
-            let length = read_half.read_u32_le().await? as usize;
+            let data = match discord::ipc::read(&mut read_half, discord::ipc::MAX_FRAME_LENGTH).await {
+                Ok(Some(data)) => data,
+                Ok(None) => {
+                    // Client disconnected
+                    tracing::info!("{} disconnected", name);
+                    break;
+                }
+                Err(e) => {
+                    tracing::error!("Could not read from {}: {}", name, e);
+                    break;
+                }
+            };
 
-            // Read the message
-            let mut buf = BytesMut::with_capacity(length);
-            let n = read_half.read_buf(&mut buf).await?;
+            tracing::trace!("Discord IPC Read: {} {} {} {}", name, data.opcode, data.len(), data.msg);
 
-            if n == 0 {
-                // Client disconnected
-                tracing::info!("{} disconnected", name);
+            if switch_tx.send((name.clone(), data)).is_err() {
+                // Switch client is gone, closing reader
                 break;
             }
-
-            match String::from_utf8(buf.to_vec()) {
-                Ok(msg) => {
-                    tracing::trace!("Discord IPC Read: {} {} {} {}", name, opcode, length, msg);
-
-                    let data = Data {
-                        opcode,
-                        msg
-                    };
-
-                    if let Err(e) = switch_tx.send(data) {
-                        tracing::error!("Could not send to mpsc channel: {}", e);
-                    }
-                },
-                Err(e) => tracing::error!("Could not decode message to UTF-8 string: {}", e),
-            }
         }
-
-        Ok::<_, io::Error>(())
     });
 
     Ok(())
 }
+
+/// `ERROR_PIPE_BUSY` from winerror.h
+const ERROR_PIPE_BUSY: i32 = 231;
+
+pub fn probe(path: &Path) -> bool {
+    match ClientOptions::new().open(path) {
+        Ok(_) => true,
+        // Every instance of the pipe is busy serving other clients
+        Err(e) => e.raw_os_error() == Some(ERROR_PIPE_BUSY),
+    }
+}
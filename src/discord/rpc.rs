@@ -0,0 +1,177 @@
+//! JSON payloads carried by IPC frames
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value, json};
+
+use crate::discord::ipc::{Data, OpCode};
+
+/// Close code sent when the switch closes a session on purpose
+pub const CLOSE_NORMAL: u32 = 1000;
+/// Close code sent when a frame is larger than allowed, as in WebSocket
+pub const CLOSE_TOO_LARGE: u32 = 1009;
+/// Close code sent when the client ID is refused
+pub const CLOSE_INVALID_CLIENT_ID: u32 = 4000;
+/// Close code sent when a client sends too much or too many clients are connected
+pub const CLOSE_RATE_LIMITED: u32 = 4002;
+
+/// Error code replied when a command fails for an unspecified reason
+pub const ERROR_UNKNOWN: u32 = 1000;
+/// Error code replied when a frame is not a valid command
+pub const ERROR_INVALID_PAYLOAD: u32 = 4000;
+/// Error code replied when a command is not permitted
+pub const ERROR_INVALID_PERMISSIONS: u32 = 4006;
+
+/// Command frame sent by an RPC client
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Command {
+    pub cmd: String,
+    #[serde(default)]
+    pub nonce: Value,
+    #[serde(flatten)]
+    pub rest: Map<String, Value>,
+}
+
+/// Payload of a Close frame
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Close {
+    pub code: u32,
+    pub message: String,
+}
+
+/// Builds a Close frame
+pub fn close(code: u32, message: &str) -> Data {
+    let close = Close {
+        code,
+        message: message.to_owned(),
+    };
+
+    Data {
+        opcode: OpCode::Close,
+        msg: json!(close).to_string(),
+    }
+}
+
+/// Builds a READY dispatch in reply to a handshake that never reaches Discord
+pub fn ready() -> Data {
+    let ready = json!({
+        "cmd": "DISPATCH",
+        "evt": "READY",
+        "nonce": null,
+        "data": {
+            "v": 1,
+            "config": {
+                "cdn_host": "cdn.discordapp.com",
+                "api_endpoint": "//discord.com/api",
+                "environment": "production",
+            },
+            "user": {
+                "id": "0",
+                "username": env!("CARGO_PKG_NAME"),
+                "discriminator": "0",
+                "global_name": null,
+                "avatar": null,
+                "bot": false,
+                "flags": 0,
+                "premium_type": 0,
+            },
+        },
+    });
+
+    Data {
+        opcode: OpCode::Frame,
+        msg: ready.to_string(),
+    }
+}
+
+/// Builds a successful reply to a command without asking Discord
+pub fn ack(cmd: &str, nonce: &Value, data: Value) -> Data {
+    let ack = json!({
+        "cmd": cmd,
+        "evt": null,
+        "nonce": nonce,
+        "data": data,
+    });
+
+    Data {
+        opcode: OpCode::Frame,
+        msg: ack.to_string(),
+    }
+}
+
+/// Builds an ERROR reply to a command
+pub fn error(cmd: &str, nonce: &Value, code: u32, message: &str) -> Data {
+    let error = json!({
+        "cmd": cmd,
+        "evt": "ERROR",
+        "nonce": nonce,
+        "data": {
+            "code": code,
+            "message": message,
+        },
+    });
+
+    Data {
+        opcode: OpCode::Frame,
+        msg: error.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn close_payload() {
+        let data = close(CLOSE_INVALID_CLIENT_ID, "Invalid Client ID");
+        assert!(matches!(data.opcode, OpCode::Close));
+
+        let close: Close = data.to_json_value().unwrap();
+        assert_eq!(close.code, 4000);
+        assert_eq!(close.message, "Invalid Client ID");
+    }
+
+    #[test]
+    fn ready_payload() {
+        let data = ready();
+        assert!(matches!(data.opcode, OpCode::Frame));
+
+        let value: Value = data.to_json_value().unwrap();
+        assert_eq!(value["cmd"], "DISPATCH");
+        assert_eq!(value["evt"], "READY");
+        assert_eq!(value["data"]["v"], 1);
+    }
+
+    #[test]
+    fn ack_echoes_nonce() {
+        let data = ack("SET_ACTIVITY", &json!("abc"), Value::Null);
+
+        let value: Value = data.to_json_value().unwrap();
+        assert_eq!(value["cmd"], "SET_ACTIVITY");
+        assert_eq!(value["nonce"], "abc");
+        assert!(value["evt"].is_null());
+        assert!(value["data"].is_null());
+    }
+
+    #[test]
+    fn error_payload() {
+        let data = error("AUTHORIZE", &json!("1"), ERROR_INVALID_PERMISSIONS, "denied");
+
+        let value: Value = data.to_json_value().unwrap();
+        assert_eq!(value["cmd"], "AUTHORIZE");
+        assert_eq!(value["evt"], "ERROR");
+        assert_eq!(value["nonce"], "1");
+        assert_eq!(value["data"]["code"], 4006);
+        assert_eq!(value["data"]["message"], "denied");
+    }
+
+    #[test]
+    fn command_preserves_fields() {
+        let msg = r#"{"cmd":"SET_ACTIVITY","args":{"pid":1},"nonce":"2"}"#;
+        let command: Command = serde_json::from_str(msg).unwrap();
+        assert_eq!(command.cmd, "SET_ACTIVITY");
+        assert_eq!(command.nonce, "2");
+
+        let value = serde_json::to_value(&command).unwrap();
+        assert_eq!(value["args"]["pid"], 1);
+    }
+}
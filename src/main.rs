@@ -1,30 +1,155 @@
+use std::process::ExitCode;
+
+use clap::Parser;
 use tokio_util::sync::CancellationToken;
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::{fmt, layer::SubscriberExt, reload};
+
+use crate::cli::{Cli, Command, RunArgs};
 
+mod cli;
+mod config;
+mod control;
 mod discord;
+mod process;
+mod sandbox;
 mod switch;
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let token = CancellationToken::new();
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    let result = match cli.command.unwrap_or(Command::Run(cli.run)) {
+        Command::Run(args) => start(args),
+        Command::List { config, ipc_dirs, json } => cli::list(config, ipc_dirs, json),
+        Command::CheckConfig { config, json } => return cli::check_config(config, json),
+        Command::Mute { instance, duration, json } => cli::mute(instance, true, duration, json),
+        Command::Unmute { instance, json } => cli::mute(instance, false, None, json),
+        Command::Cache { action, json } => cli::cache(action, json),
+        Command::Profile { name, default, json } => cli::profile(name, default, json),
+        Command::Version { json } => {
+            cli::version(json);
+            Ok(())
+        }
+    };
 
-    // Set up logging with tracing
-    let subscriber = tracing_subscriber::fmt()
-        .with_max_level(tracing::Level::TRACE)
-        .finish();
+    // Report errors with their messages rather than their debug representation
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn start(args: RunArgs) -> Result<(), Box<dyn std::error::Error>> {
+    // Set up logging with tracing, at a level the config may change
+    let (filter, log) = reload::Layer::new(LevelFilter::TRACE);
+    let subscriber = tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt::layer());
     tracing::subscriber::set_global_default(subscriber)?;
 
+    let source = args.source();
+    let config = source.load()?;
+    log.modify(|filter| *filter = config.log.level)?;
+
+    // Sandbox before the runtime spawns its threads so they inherit it
+    sandbox::apply(&config, source.dir());
+
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?
+        .block_on(run(source, config, log))
+}
+
+/// Resolves on Ctrl+C, or on SIGTERM where there is one, such as when systemd stops the service
+async fn interrupted() {
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(e) => {
+                tracing::error!("Unable to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => match result {
+            Ok(_) => tracing::info!("Received Ctrl+C"),
+            Err(e) => tracing::error!("Unable to listen for shutdown signal: {}", e),
+        },
+        _ = terminate => tracing::info!("Received SIGTERM"),
+    }
+}
+
+async fn run(
+    source: config::Source,
+    config: config::Config,
+    log: reload::Handle<LevelFilter, tracing_subscriber::Registry>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let token = CancellationToken::new();
+
     // Handle interrupts
     let interrupt_token = token.clone();
     tokio::spawn(async move {
-        match tokio::signal::ctrl_c().await {
-            Ok(_) => tracing::info!("Received Ctrl+C"),
-            Err(e) => tracing::error!("Unable to listen for shutdown signal: {}", e),
+        interrupted().await;
+        interrupt_token.cancel();
+    });
+
+    if discord::api::offline(&config.api) {
+        tracing::info!("Offline, application metadata is only read from the cache");
+    }
+
+    let path = source.path.clone();
+    let server = switch::ipc::Server::new(token.clone(), source, config);
+
+    // Reload the config when it changes
+    let reload_server = server.clone();
+    tokio::spawn(config::watch::watch(path, token.clone(), move || {
+        let _ = reload_server.reload();
+    }));
+
+    // Follow the log level of reloaded configs
+    let mut configs = server.subscribe();
+    tokio::spawn(async move {
+        while configs.changed().await.is_ok() {
+            let level = configs.borrow_and_update().log.level;
+            if let Err(e) = log.modify(|filter| *filter = level) {
+                tracing::error!("Unable to change log level: {}", e);
+            }
         }
+    });
 
-        interrupt_token.cancel();
+    // Switch profiles as matching processes start and stop
+    tokio::spawn(switch::autoprofile::watch(server.clone()));
+
+    // Apply quiet hours as they start and end
+    tokio::spawn(switch::schedule::watch(server.clone()));
+
+    // Write out the metadata cache now and then, and before exiting
+    let cache = tokio::spawn(discord::api::cache::watch(token.clone()));
+
+    // Serve the control socket alongside the switch
+    let control_server = server.clone();
+    let control = tokio::spawn(async move {
+        if let Err(e) = control::start(control_server).await {
+            tracing::error!("Unable to serve the control socket: {}", e);
+        }
     });
 
     // Start the switch IPC server
-    let server = switch::ipc::Server::new(token.clone())?;
-    server.start().await
+    server.start().await?;
+
+    // Let the control socket and cache clean up after themselves
+    let _ = control.await;
+    let _ = cache.await;
+    Ok(())
 }
@@ -0,0 +1,100 @@
+use std::{collections::HashSet, ffi::{CStr, CString}, fs, io, mem::MaybeUninit, os::fd::{FromRawFd, OwnedFd, RawFd}, path::Path, ptr};
+
+use tokio::io::{Interest, unix::AsyncFd};
+
+pub const PROC: &str = "/proc";
+
+pub fn running() -> HashSet<String> {
+    let mut names = HashSet::new();
+
+    let Ok(entries) = fs::read_dir(PROC) else {
+        tracing::warn!("Unable to list processes in {}", PROC);
+        return names;
+    };
+
+    // Processes may exit while we read them, so unreadable ones are skipped
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if !entry.file_name().to_string_lossy().bytes().all(|b| b.is_ascii_digit()) {
+            continue;
+        }
+
+        if let Ok(comm) = fs::read_to_string(path.join("comm")) {
+            names.insert(comm.trim_end().to_owned());
+        }
+        if let Some(name) = executable(&path) {
+            names.insert(name);
+        }
+    }
+
+    names
+}
+
+/// File name of the first argument a process was started with, which is usually its executable
+fn executable(path: &Path) -> Option<String> {
+    let cmdline = fs::read(path.join("cmdline")).ok()?;
+    let argv0 = cmdline.split(|b| *b == 0).next().filter(|argv0| !argv0.is_empty())?;
+    let argv0 = String::from_utf8_lossy(argv0);
+
+    Path::new(argv0.as_ref()).file_name().map(|name| name.to_string_lossy().into_owned())
+}
+
+/// The primary and supplementary groups of a user, or just the primary one if the user can't be
+/// looked up
+pub fn groups(uid: u32, gid: u32) -> Vec<u32> {
+    let Some(name) = user_name(uid) else {
+        return vec![gid];
+    };
+
+    let mut count: libc::c_int = 32;
+    loop {
+        let mut groups = vec![0; count as usize];
+        let capacity = count;
+        // SAFETY: groups holds count entries, and count is set to how many there are
+        let result = unsafe { libc::getgrouplist(name.as_ptr(), gid, groups.as_mut_ptr(), &mut count) };
+        if result >= 0 {
+            groups.truncate(count as usize);
+            return groups;
+        }
+        // Too few entries, unless the lookup failed without saying how many are needed
+        if count <= capacity {
+            return vec![gid];
+        }
+    }
+}
+
+fn user_name(uid: u32) -> Option<CString> {
+    let mut buffer = vec![0 as libc::c_char; 1024];
+    loop {
+        let mut passwd = MaybeUninit::<libc::passwd>::uninit();
+        let mut result = ptr::null_mut();
+        // SAFETY: the buffer outlives the lookup, and pw_name points into it
+        let status = unsafe { libc::getpwuid_r(uid, passwd.as_mut_ptr(), buffer.as_mut_ptr(), buffer.len(), &mut result) };
+        match status {
+            0 if !result.is_null() => return Some(unsafe { CStr::from_ptr((*result).pw_name) }.to_owned()),
+            libc::ERANGE if buffer.len() < 1 << 20 => buffer.resize(buffer.len() * 2, 0),
+            _ => return None,
+        }
+    }
+}
+
+/// Waits on a pidfd, which becomes readable when the process exits
+pub async fn exited(pid: i32) -> io::Result<()> {
+    // SAFETY: pidfd_open takes no pointers
+    let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid, 0) };
+    if fd < 0 {
+        let e = io::Error::last_os_error();
+        // Already gone
+        return match e.raw_os_error() {
+            Some(libc::ESRCH) => Ok(()),
+            _ => Err(e),
+        };
+    }
+
+    // SAFETY: pidfd_open returned a new descriptor that nothing else owns
+    let fd = unsafe { OwnedFd::from_raw_fd(fd as RawFd) };
+    let fd = AsyncFd::with_interest(fd, Interest::READABLE)?;
+    let _ = fd.readable().await?;
+
+    Ok(())
+}
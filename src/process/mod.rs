@@ -0,0 +1,92 @@
+use std::collections::HashSet;
+
+#[cfg(target_os = "linux")]
+mod linux;
+
+/// Where running processes are listed, which the sandbox must leave readable for `running`
+#[cfg(target_os = "linux")]
+pub const PROC: &str = linux::PROC;
+
+/// Names of the processes currently running, both their `comm` and the file name of their
+/// executable, or `None` where processes can't be listed
+pub fn running() -> Option<HashSet<String>> {
+    #[cfg(target_os = "linux")]
+    return Some(linux::running());
+
+    #[cfg(not(target_os = "linux"))]
+    None
+}
+
+/// The groups a user belongs to, supplementary ones included where they can be looked up
+pub fn groups(uid: u32, gid: u32) -> Vec<u32> {
+    #[cfg(target_os = "linux")]
+    return linux::groups(uid, gid);
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = uid;
+        vec![gid]
+    }
+}
+
+/// Resolves once a process exits, or never where that can't be watched
+pub async fn exited(pid: i32) {
+    #[cfg(target_os = "linux")]
+    match linux::exited(pid).await {
+        Ok(()) => return,
+        Err(e) => tracing::warn!("Unable to watch process {}: {}", pid, e),
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    let _ = pid;
+
+    std::future::pending().await
+}
+
+/// Whether a process named `name` is among those `running`. The kernel truncates `comm` to 15
+/// bytes, so longer names also match their truncated form.
+pub fn matches(running: &HashSet<String>, name: &str) -> bool {
+    if running.contains(name) {
+        return true;
+    }
+
+    name.len() > COMM_LENGTH && name.is_char_boundary(COMM_LENGTH) && running.contains(&name[..COMM_LENGTH])
+}
+
+/// Longest `comm` the kernel keeps
+const COMM_LENGTH: usize = 15;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn own_groups() {
+        // SAFETY: getuid and getgid are always successful
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+        assert!(groups(uid, gid).contains(&gid));
+        // Users that don't exist only have their primary group
+        assert_eq!(groups(u32::MAX - 1, 12345), vec![12345]);
+    }
+
+    #[test]
+    fn matches_truncated_comm() {
+        let running = HashSet::from([String::from("obs"), String::from("jetbrains-toolb")]);
+
+        assert!(matches(&running, "obs"));
+        assert!(matches(&running, "jetbrains-toolbox"));
+        assert!(!matches(&running, "ob"));
+        assert!(!matches(&running, "code"));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn exited_process() {
+        let mut child = std::process::Command::new("sleep").arg("0.1").spawn().unwrap();
+        let pid = child.id() as i32;
+
+        tokio::time::timeout(std::time::Duration::from_secs(5), exited(pid)).await.unwrap();
+        child.wait().unwrap();
+    }
+}
@@ -0,0 +1,47 @@
+use std::fmt;
+
+#[derive(Debug)]
+pub enum SandboxError {
+    Landlock(landlock::RulesetError),
+    Seccomp(seccompiler::Error),
+}
+
+impl fmt::Display for SandboxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SandboxError::Landlock(e) => write!(f, "unable to apply Landlock: {}", e),
+            SandboxError::Seccomp(e) => write!(f, "unable to apply seccomp filter: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for SandboxError {}
+
+impl From<landlock::RulesetError> for SandboxError {
+    fn from(e: landlock::RulesetError) -> Self {
+        SandboxError::Landlock(e)
+    }
+}
+
+impl From<seccompiler::Error> for SandboxError {
+    fn from(e: seccompiler::Error) -> Self {
+        SandboxError::Seccomp(e)
+    }
+}
+
+impl From<seccompiler::BackendError> for SandboxError {
+    fn from(e: seccompiler::BackendError) -> Self {
+        SandboxError::Seccomp(seccompiler::Error::Backend(e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sandbox_error_display() {
+        let err = SandboxError::Seccomp(seccompiler::Error::Prctl(std::io::Error::from_raw_os_error(libc::EINVAL)));
+        assert!(format!("{}", err).starts_with("unable to apply seccomp filter: "));
+    }
+}
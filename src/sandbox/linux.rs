@@ -0,0 +1,197 @@
+use std::{collections::BTreeMap, path::{Path, PathBuf}};
+
+use landlock::{ABI, Access, AccessFs, Ruleset, RulesetAttr, RulesetCreatedAttr, RulesetStatus, path_beneath_rules};
+use seccompiler::{BpfProgram, SeccompAction, SeccompCmpArgLen, SeccompCmpOp, SeccompCondition, SeccompFilter, SeccompRule};
+
+use crate::{discord, sandbox::error::SandboxError};
+
+/// Newest Landlock ABI we know how to use; older kernels get a best-effort subset
+const LANDLOCK_ABI: ABI = ABI::V5;
+
+/// Files read when resolving discord.com and verifying its certificate
+const NETWORK_PATHS: [&str; 10] = [
+    "/etc/hosts",
+    "/etc/host.conf",
+    "/etc/nsswitch.conf",
+    "/etc/gai.conf",
+    "/etc/resolv.conf",
+    "/etc/ssl",
+    "/etc/pki",
+    "/etc/ca-certificates",
+    "/usr/share/ca-certificates",
+    "/usr/lib/ssl",
+];
+
+/// Time zone data, for quiet hours given in local time
+const TIME_PATHS: [&str; 2] = ["/etc/localtime", "/usr/share/zoneinfo"];
+
+/// Shared libraries loaded at runtime, such as NSS modules used by the resolver
+const LIBRARY_PATHS: [&str; 4] = ["/lib", "/lib64", "/usr/lib", "/usr/lib64"];
+
+/// Syscalls used by tokio, the resolver, TLS and the relay itself
+const SYSCALLS: &[libc::c_long] = &[
+    // Memory
+    libc::SYS_brk,
+    libc::SYS_mmap,
+    libc::SYS_mprotect,
+    libc::SYS_mremap,
+    libc::SYS_munmap,
+    libc::SYS_madvise,
+    // Threads and synchronization
+    libc::SYS_clone,
+    libc::SYS_clone3,
+    libc::SYS_futex,
+    libc::SYS_set_robust_list,
+    libc::SYS_rseq,
+    libc::SYS_sched_getaffinity,
+    libc::SYS_sched_yield,
+    libc::SYS_prctl,
+    libc::SYS_exit,
+    libc::SYS_exit_group,
+    // Signals
+    libc::SYS_rt_sigaction,
+    libc::SYS_rt_sigprocmask,
+    libc::SYS_rt_sigreturn,
+    libc::SYS_sigaltstack,
+    libc::SYS_tgkill,
+    // Time
+    libc::SYS_clock_gettime,
+    libc::SYS_clock_nanosleep,
+    libc::SYS_nanosleep,
+    // Identity
+    libc::SYS_getpid,
+    libc::SYS_gettid,
+    libc::SYS_getuid,
+    libc::SYS_geteuid,
+    libc::SYS_getgid,
+    libc::SYS_getegid,
+    libc::SYS_getrandom,
+    libc::SYS_uname,
+    libc::SYS_prlimit64,
+    // Files
+    libc::SYS_openat,
+    libc::SYS_close,
+    libc::SYS_read,
+    libc::SYS_pread64,
+    libc::SYS_readv,
+    libc::SYS_write,
+    libc::SYS_writev,
+    libc::SYS_lseek,
+    libc::SYS_fstat,
+    libc::SYS_newfstatat,
+    libc::SYS_statx,
+    libc::SYS_faccessat,
+    libc::SYS_faccessat2,
+    libc::SYS_readlinkat,
+    libc::SYS_getdents64,
+    libc::SYS_fchmod,
+    libc::SYS_fchmodat,
+    libc::SYS_fchown,
+    libc::SYS_fchownat,
+    libc::SYS_umask,
+    libc::SYS_mkdirat,
+    libc::SYS_unlinkat,
+    libc::SYS_renameat2,
+    libc::SYS_fcntl,
+    libc::SYS_ioctl,
+    libc::SYS_dup,
+    libc::SYS_dup3,
+    libc::SYS_pipe2,
+    libc::SYS_eventfd2,
+    libc::SYS_pidfd_open,
+    // Polling
+    libc::SYS_epoll_create1,
+    libc::SYS_epoll_ctl,
+    libc::SYS_epoll_pwait,
+    libc::SYS_epoll_pwait2,
+    libc::SYS_ppoll,
+    // Sockets
+    libc::SYS_socket,
+    libc::SYS_socketpair,
+    libc::SYS_bind,
+    libc::SYS_listen,
+    libc::SYS_accept4,
+    libc::SYS_connect,
+    libc::SYS_shutdown,
+    libc::SYS_getsockname,
+    libc::SYS_getpeername,
+    libc::SYS_getsockopt,
+    libc::SYS_setsockopt,
+    libc::SYS_sendto,
+    libc::SYS_sendmsg,
+    libc::SYS_sendmmsg,
+    libc::SYS_recvfrom,
+    libc::SYS_recvmsg,
+    libc::SYS_recvmmsg,
+];
+
+/// Legacy variants of the above that only exist on x86_64
+#[cfg(target_arch = "x86_64")]
+const LEGACY_SYSCALLS: &[libc::c_long] = &[
+    libc::SYS_arch_prctl,
+    libc::SYS_open,
+    libc::SYS_stat,
+    libc::SYS_lstat,
+    libc::SYS_access,
+    libc::SYS_readlink,
+    libc::SYS_unlink,
+    libc::SYS_chmod,
+    libc::SYS_chown,
+    libc::SYS_mkdir,
+    libc::SYS_rename,
+    libc::SYS_poll,
+    libc::SYS_epoll_wait,
+    libc::SYS_pipe,
+];
+
+#[cfg(not(target_arch = "x86_64"))]
+const LEGACY_SYSCALLS: &[libc::c_long] = &[];
+
+/// Limits filesystem access to the IPC directory and `write_dirs`, and reading to the config directory,
+/// other `read_dirs` and, with `network`, what is needed to reach the Discord API, returning whether
+/// the kernel enforces it
+pub fn landlock(config_dir: Option<&Path>, read_dirs: &[PathBuf], write_dirs: &[PathBuf], network: bool) -> Result<bool, SandboxError> {
+    let all = AccessFs::from_all(LANDLOCK_ABI);
+    let read = AccessFs::from_read(LANDLOCK_ABI);
+
+    let read_paths = config_dir
+        .map(Path::to_path_buf)
+        .into_iter()
+        .chain(read_dirs.iter().cloned())
+        .chain(NETWORK_PATHS.iter().filter(|_| network).chain(TIME_PATHS.iter()).chain(LIBRARY_PATHS.iter()).map(PathBuf::from));
+
+    let status = Ruleset::default()
+        .handle_access(all)?
+        .create()?
+        .add_rules(path_beneath_rules(write_dirs.iter().cloned().chain([discord::ipc::dir()]), all))?
+        .add_rules(path_beneath_rules(read_paths, read))?
+        .restrict_self()?;
+
+    Ok(status.ruleset != RulesetStatus::NotEnforced)
+}
+
+/// Makes any syscall outside of `SYSCALLS` fail with `EPERM`. Without `network`, only Unix
+/// sockets can be created.
+pub fn seccomp(network: bool) -> Result<(), SandboxError> {
+    let mut rules = SYSCALLS
+        .iter()
+        .chain(LEGACY_SYSCALLS)
+        .map(|syscall| (*syscall, Vec::new()))
+        .collect::<BTreeMap<_, _>>();
+
+    if !network {
+        let unix = SeccompCondition::new(0, SeccompCmpArgLen::Dword, SeccompCmpOp::Eq, libc::AF_UNIX as u64)?;
+        rules.insert(libc::SYS_socket, vec![SeccompRule::new(vec![unix])?]);
+    }
+
+    let filter = SeccompFilter::new(
+        rules,
+        SeccompAction::Errno(libc::EPERM as u32),
+        SeccompAction::Allow,
+        std::env::consts::ARCH.try_into()?,
+    )?;
+    let program: BpfProgram = filter.try_into()?;
+    seccompiler::apply_filter(&program)?;
+
+    Ok(())
+}
@@ -0,0 +1,99 @@
+use std::path::Path;
+
+use crate::config;
+
+#[cfg(target_os = "linux")]
+pub mod error;
+
+#[cfg(target_os = "linux")]
+mod linux;
+
+/// Restricts what the daemon can access for the rest of its life, falling back to running
+/// unrestricted where the platform or kernel lacks support.
+///
+/// `config_dir` stays readable so the config can be reloaded. Must be called before any threads
+/// are spawned, as restrictions are only inherited by threads created afterwards.
+pub fn apply(config: &config::Config, config_dir: Option<&Path>) {
+    if !config.sandbox.enabled {
+        return;
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        let mut read_dirs = config.server.ipc_dirs.clone();
+        // Rules added by a later reload can't widen the sandbox, so they need a restart
+        if !config.auto_profile.rules.is_empty() {
+            read_dirs.push(std::path::PathBuf::from(crate::process::PROC));
+        }
+        // Peers' supplementary groups are looked up in the user and group databases
+        if !config.peer.gids.is_empty() {
+            read_dirs.extend(["/etc/passwd", "/etc/group", "/etc/nsswitch.conf"].map(std::path::PathBuf::from));
+        }
+
+        // The cache must exist to be allowed
+        let mut write_dirs = crate::discord::api::cache::dir()
+            .filter(|dir| std::fs::create_dir_all(dir).is_ok())
+            .into_iter()
+            .collect::<Vec<_>>();
+        // A switch socket named by full path lives outside of the runtime directory
+        let name = Path::new(&config.server.name);
+        if name.is_absolute()
+            && let Some(dir) = name.parent()
+        {
+            write_dirs.push(dir.to_path_buf());
+        }
+
+        // Like rules, going offline on reload can't narrow the sandbox until a restart
+        let network = !crate::discord::api::offline(&config.api);
+
+        match linux::landlock(config_dir, &read_dirs, &write_dirs, network) {
+            Ok(true) => tracing::info!("Landlock filesystem restrictions applied"),
+            Ok(false) => tracing::warn!("Landlock is not supported by this kernel, filesystem access is unrestricted"),
+            Err(e) => tracing::warn!("{}, filesystem access is unrestricted", e),
+        }
+
+        match linux::seccomp(network) {
+            Ok(()) => tracing::info!("Seccomp syscall filter applied"),
+            Err(e) => tracing::warn!("{}, syscalls are unrestricted", e),
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    tracing::warn!("Sandboxing is only supported on Linux, running unrestricted");
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn listen_sandboxed() {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+        let dir = std::env::temp_dir().join(format!("presence-switch-sandbox-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("discord-ipc-0");
+
+        // SAFETY: getgid is always successful
+        let gid = unsafe { libc::getgid() };
+        let mut config = crate::config::Config::default();
+        config.sandbox.enabled = true;
+        config.server.name = path.display().to_string();
+        config.peer.gids = vec![gid];
+
+        // Restrictions only apply to the thread that applies them, leaving other tests alone
+        let (mode, owner) = std::thread::spawn(move || {
+            super::apply(&config, None);
+            let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+            let _listener = runtime.block_on(crate::discord::ipc::listen(&path, &config.peer)).unwrap();
+            let metadata = std::fs::metadata(&path).unwrap();
+            (metadata.permissions().mode() & 0o777, metadata.gid())
+        })
+        .join()
+        .unwrap();
+
+        assert_eq!(mode, 0o660);
+        assert_eq!(owner, gid);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
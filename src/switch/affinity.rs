@@ -0,0 +1,106 @@
+use std::collections::HashMap;
+
+use serde_json::Value;
+
+/// Events a Discord instance dispatches that the client is expected to reply to
+const EVENTS: [&str; 1] = ["ACTIVITY_JOIN_REQUEST"];
+
+/// Commands replying to one of `EVENTS`
+const REPLIES: [&str; 2] = ["SEND_ACTIVITY_JOIN_INVITE", "CLOSE_ACTIVITY_REQUEST"];
+
+/// Remembers which Discord instance dispatched each event so replies only go back to it
+#[derive(Debug, Default)]
+pub struct Affinity {
+    /// Instance names keyed by the requesting user's ID
+    users: HashMap<String, String>,
+}
+
+impl Affinity {
+    /// Records the instance that dispatched a frame if it is one the client may reply to
+    pub fn record(&mut self, instance: &str, frame: &Value) {
+        if frame["cmd"] != "DISPATCH" {
+            return;
+        }
+
+        let Some(evt) = frame["evt"].as_str().filter(|evt| EVENTS.contains(evt)) else {
+            return;
+        };
+
+        tracing::debug!("{} dispatched {}", instance, evt);
+
+        if let Some(user_id) = frame["data"]["user"]["id"].as_str() {
+            self.users.insert(user_id.to_owned(), instance.to_owned());
+        }
+    }
+
+    /// Takes the instance a reply command should be sent to, if it replies to a recorded event.
+    /// Replies to users no instance asked about go to every instance.
+    pub fn take(&mut self, cmd: &str, args: &Value) -> Option<String> {
+        if !REPLIES.contains(&cmd) {
+            return None;
+        }
+
+        self.users.remove(args["user_id"].as_str()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn join_request(user_id: &str) -> Value {
+        json!({
+            "cmd": "DISPATCH",
+            "evt": "ACTIVITY_JOIN_REQUEST",
+            "data": { "user": { "id": user_id } },
+        })
+    }
+
+    #[test]
+    fn reply_goes_to_requesting_instance() {
+        let mut affinity = Affinity::default();
+        affinity.record("discord-ipc-1", &join_request("10"));
+        affinity.record("discord-ipc-2", &join_request("20"));
+
+        let args = json!({ "user_id": "10" });
+        assert_eq!(affinity.take("SEND_ACTIVITY_JOIN_INVITE", &args).as_deref(), Some("discord-ipc-1"));
+
+        let args = json!({ "user_id": "20" });
+        assert_eq!(affinity.take("CLOSE_ACTIVITY_REQUEST", &args).as_deref(), Some("discord-ipc-2"));
+    }
+
+    #[test]
+    fn reply_without_known_user_has_no_affinity() {
+        let mut affinity = Affinity::default();
+        affinity.record("discord-ipc-2", &join_request("20"));
+
+        let args = json!({ "user_id": "30" });
+        assert!(affinity.take("SEND_ACTIVITY_JOIN_INVITE", &args).is_none());
+        assert!(affinity.take("SEND_ACTIVITY_JOIN_INVITE", &json!({})).is_none());
+
+        // Each request is only replied to once
+        let args = json!({ "user_id": "20" });
+        assert_eq!(affinity.take("SEND_ACTIVITY_JOIN_INVITE", &args).as_deref(), Some("discord-ipc-2"));
+        assert!(affinity.take("SEND_ACTIVITY_JOIN_INVITE", &args).is_none());
+    }
+
+    #[test]
+    fn unrelated_frames_are_ignored() {
+        let mut affinity = Affinity::default();
+        affinity.record("discord-ipc-1", &json!({ "cmd": "DISPATCH", "evt": "READY" }));
+        affinity.record("discord-ipc-1", &json!({ "cmd": "SET_ACTIVITY", "evt": null }));
+
+        let args = json!({ "user_id": "10" });
+        assert!(affinity.take("SEND_ACTIVITY_JOIN_INVITE", &args).is_none());
+    }
+
+    #[test]
+    fn other_commands_have_no_affinity() {
+        let mut affinity = Affinity::default();
+        affinity.record("discord-ipc-1", &join_request("10"));
+
+        assert!(affinity.take("SET_ACTIVITY", &json!({})).is_none());
+    }
+}
@@ -0,0 +1,140 @@
+use std::{cmp::Reverse, collections::HashSet, time::{Duration, Instant}};
+
+use crate::{config::ProfileRule, process, switch::ipc::{Server, Trigger}};
+
+/// Activates the profile of the highest priority rule whose processes are running, or the config's
+/// `profile` when none are, until the server is cancelled.
+///
+/// Only changes in which rule matches cause a switch, so a profile chosen manually stays active
+/// until a rule starts or stops matching.
+pub async fn watch(server: Server) {
+    // The config's profile is what is active at startup
+    let mut debounce = Debounce::new(None);
+
+    loop {
+        let config = server.config();
+        let interval = Duration::from_secs(config.auto_profile.interval);
+
+        tokio::select! {
+            _ = server.token().cancelled() => break,
+            _ = tokio::time::sleep(interval) => {}
+        }
+
+        let rules = &config.auto_profile.rules;
+        if rules.is_empty() {
+            continue;
+        }
+
+        let Ok(Some(running)) = tokio::task::spawn_blocking(process::running).await else {
+            tracing::warn!("Unable to list running processes, automatic profile switching is disabled");
+            break;
+        };
+
+        let selected = select(rules, &running);
+        let debounce_period = Duration::from_secs(config.auto_profile.debounce);
+        let Some(profile) = debounce.update(selected.map(|(profile, _)| profile.to_owned()), Instant::now(), debounce_period) else {
+            continue;
+        };
+
+        let (name, trigger) = match selected {
+            Some((_, process)) => (profile, Trigger::Process(process.to_owned())),
+            None => (config.profile.clone(), Trigger::Config),
+        };
+        server.set_profile(name.as_deref(), trigger);
+    }
+}
+
+/// The profile of the highest priority rule with a running process, first listed on ties, and
+/// the process that matched
+pub fn select<'a>(rules: &'a [ProfileRule], running: &HashSet<String>) -> Option<(&'a str, &'a str)> {
+    rules
+        .iter()
+        .enumerate()
+        .filter_map(|(i, rule)| {
+            let process = rule.processes.iter().find(|name| process::matches(running, name))?;
+            Some((i, rule, process))
+        })
+        .max_by_key(|(i, rule, _)| (rule.priority, Reverse(*i)))
+        .map(|(_, rule, process)| (rule.profile.as_str(), process.as_str()))
+}
+
+/// Holds back a changing value until it has stayed the same for a period
+#[derive(Debug)]
+struct Debounce<T> {
+    applied: T,
+    pending: Option<(T, Instant)>,
+}
+
+impl<T: Clone + PartialEq> Debounce<T> {
+    fn new(applied: T) -> Debounce<T> {
+        Debounce { applied, pending: None }
+    }
+
+    /// Records the latest value, returning it once it should be applied
+    fn update(&mut self, value: T, now: Instant, period: Duration) -> Option<T> {
+        if value == self.applied {
+            self.pending = None;
+            return None;
+        }
+
+        let since = match &self.pending {
+            Some((pending, since)) if *pending == value => *since,
+            _ => now,
+        };
+        if now.duration_since(since) < period {
+            self.pending = Some((value, since));
+            return None;
+        }
+
+        self.pending = None;
+        self.applied = value.clone();
+        Some(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(profile: &str, processes: &[&str], priority: i32) -> ProfileRule {
+        ProfileRule {
+            profile: profile.to_owned(),
+            processes: processes.iter().map(|name| name.to_string()).collect(),
+            priority,
+        }
+    }
+
+    #[test]
+    fn select_by_priority() {
+        let rules = [rule("work", &["code", "openvpn"], 0), rule("streaming", &["obs"], 10), rule("gaming", &["steam"], 10)];
+        let running = |names: &[&str]| names.iter().map(|name| name.to_string()).collect::<HashSet<_>>();
+
+        assert_eq!(select(&rules, &running(&["bash"])), None);
+        assert_eq!(select(&rules, &running(&["openvpn"])), Some(("work", "openvpn")));
+        assert_eq!(select(&rules, &running(&["code", "obs"])), Some(("streaming", "obs")));
+        // Equal priorities go to the first listed
+        assert_eq!(select(&rules, &running(&["steam", "obs"])), Some(("streaming", "obs")));
+    }
+
+    #[test]
+    fn debounce() {
+        let start = Instant::now();
+        let period = Duration::from_secs(10);
+        let mut debounce = Debounce::new(None);
+
+        assert_eq!(debounce.update(None, start, period), None);
+        assert_eq!(debounce.update(Some("streaming"), start, period), None);
+        assert_eq!(debounce.update(Some("streaming"), start + Duration::from_secs(9), period), None);
+        assert_eq!(debounce.update(Some("streaming"), start + period, period), Some(Some("streaming")));
+        assert_eq!(debounce.update(Some("streaming"), start + period * 2, period), None);
+
+        // A brief change restarts the period
+        assert_eq!(debounce.update(None, start + period * 3, period), None);
+        assert_eq!(debounce.update(Some("streaming"), start + period * 3, period), None);
+        assert_eq!(debounce.update(None, start + period * 4, period), None);
+        assert_eq!(debounce.update(None, start + period * 5, period), Some(None));
+
+        // Without a period, changes apply at once
+        assert_eq!(debounce.update(Some("work"), start, Duration::ZERO), Some(Some("work")));
+    }
+}
@@ -0,0 +1,285 @@
+use std::{collections::HashMap, time::{Duration, Instant}};
+
+use serde_json::Value;
+
+use crate::discord::{ipc::Data, rpc};
+
+/// A command relayed to one or more Discord instances
+#[derive(Debug)]
+struct Request {
+    cmd: String,
+    /// Nonce the client sent
+    nonce: Value,
+    /// Instances yet to answer
+    outstanding: usize,
+    /// Whether the client has been answered
+    answered: bool,
+    /// First error answered, forwarded if no instance succeeds
+    error: Option<Data>,
+    /// Sent by the switch itself, so replies are never forwarded
+    internal: bool,
+}
+
+/// A request sent to a single instance under a rewritten nonce
+#[derive(Debug)]
+struct Pending {
+    request: u64,
+    instance: String,
+    sent_at: Instant,
+}
+
+/// Gives every command relayed to each Discord instance its own nonce so replies can be matched to
+/// the instance that sent them, then restores the client's nonce on the reply.
+///
+/// A command sent to several instances is answered once: with the first successful reply, or with
+/// an error once every instance has failed or timed out.
+#[derive(Debug, Default)]
+pub struct Correlation {
+    next: u64,
+    requests: HashMap<u64, Request>,
+    pending: HashMap<String, Pending>,
+}
+
+impl Correlation {
+    /// Starts tracking a command sent to `instances`, returning the nonce to send each of them
+    pub fn begin(&mut self, command: &rpc::Command, instances: &[String]) -> Vec<String> {
+        self.track(command, instances, false)
+    }
+
+    /// Starts tracking a command the switch sends on its own behalf
+    pub fn begin_internal(&mut self, command: &rpc::Command, instances: &[String]) -> Vec<String> {
+        self.track(command, instances, true)
+    }
+
+    fn track(&mut self, command: &rpc::Command, instances: &[String], internal: bool) -> Vec<String> {
+        if instances.is_empty() {
+            return Vec::new();
+        }
+
+        self.next += 1;
+        let request = self.next;
+
+        self.requests.insert(request, Request {
+            cmd: command.cmd.clone(),
+            nonce: command.nonce.clone(),
+            outstanding: instances.len(),
+            answered: false,
+            error: None,
+            internal,
+        });
+
+        instances
+            .iter()
+            .map(|instance| {
+                let nonce = format!("{}-{}", request, instance);
+                self.pending.insert(nonce.clone(), Pending {
+                    request,
+                    instance: instance.clone(),
+                    sent_at: Instant::now(),
+                });
+                nonce
+            })
+            .collect()
+    }
+
+    /// Matches a reply from an instance to its request, returning the frame to forward to the client
+    pub fn reply(&mut self, data: Data) -> Option<Data> {
+        let Ok(mut frame) = data.to_json_value::<Value>() else {
+            return Some(data);
+        };
+
+        // Frames that aren't replies to a tracked command, such as dispatched events, pass through
+        let Some(pending) = frame["nonce"].as_str().and_then(|nonce| self.pending.remove(nonce)) else {
+            return Some(data);
+        };
+        let request = self.requests.get(&pending.request)?;
+
+        frame["nonce"] = request.nonce.clone();
+        let error = frame["evt"] == "ERROR";
+        let data = Data {
+            opcode: data.opcode,
+            msg: frame.to_string(),
+        };
+
+        self.settle(pending.request, data, error)
+    }
+
+    /// Records that an instance will never answer, returning the frame to forward to the client
+    pub fn fail(&mut self, nonce: &str, message: &str) -> Option<Data> {
+        let pending = self.pending.remove(nonce)?;
+        let request = self.requests.get(&pending.request)?;
+        let data = rpc::error(&request.cmd, &request.nonce, rpc::ERROR_UNKNOWN, message);
+
+        self.settle(pending.request, data, true)
+    }
+
+    /// Fails every request an instance has not answered within `timeout`
+    pub fn expire(&mut self, timeout: Duration) -> Vec<Data> {
+        let expired = self
+            .pending
+            .iter()
+            .filter(|(_, pending)| pending.sent_at.elapsed() >= timeout)
+            .map(|(nonce, pending)| (nonce.clone(), pending.instance.clone()))
+            .collect::<Vec<_>>();
+
+        expired
+            .into_iter()
+            .filter_map(|(nonce, instance)| {
+                let message = format!("{} did not answer within {}s", instance, timeout.as_secs());
+                tracing::warn!("Request {}: {}", nonce, message);
+                self.fail(&nonce, &message)
+            })
+            .collect()
+    }
+
+    fn settle(&mut self, id: u64, data: Data, error: bool) -> Option<Data> {
+        let request = self.requests.get_mut(&id)?;
+        request.outstanding -= 1;
+
+        let forward = if request.internal {
+            if error {
+                tracing::warn!("{} failed: {}", request.cmd, data.msg);
+            }
+            None
+        } else if request.answered {
+            None
+        } else if !error {
+            request.answered = true;
+            Some(data)
+        } else {
+            request.error.get_or_insert(data);
+            if request.outstanding == 0 {
+                request.answered = true;
+                request.error.take()
+            } else {
+                None
+            }
+        };
+
+        if request.outstanding == 0 {
+            self.requests.remove(&id);
+        }
+
+        forward
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::discord::ipc::OpCode;
+
+    fn command(nonce: &str) -> rpc::Command {
+        serde_json::from_value(json!({ "cmd": "SET_ACTIVITY", "nonce": nonce })).unwrap()
+    }
+
+    fn instances() -> Vec<String> {
+        vec![String::from("discord-ipc-1"), String::from("discord-ipc-2")]
+    }
+
+    fn reply(nonce: &str, evt: Value) -> Data {
+        Data {
+            opcode: OpCode::Frame,
+            msg: json!({ "cmd": "SET_ACTIVITY", "evt": evt, "nonce": nonce, "data": null }).to_string(),
+        }
+    }
+
+    fn nonce(data: &Data) -> Value {
+        data.to_json_value::<Value>().unwrap()["nonce"].clone()
+    }
+
+    #[test]
+    fn nonces_are_unique_per_instance() {
+        let mut correlation = Correlation::default();
+        let first = correlation.begin(&command("a"), &instances());
+        let second = correlation.begin(&command("a"), &instances());
+
+        assert_eq!(first.len(), 2);
+        assert_ne!(first[0], first[1]);
+        assert!(first.iter().all(|nonce| !second.contains(nonce)));
+    }
+
+    #[test]
+    fn first_success_is_forwarded_with_original_nonce() {
+        let mut correlation = Correlation::default();
+        let nonces = correlation.begin(&command("a"), &instances());
+
+        let data = correlation.reply(reply(&nonces[1], Value::Null)).unwrap();
+        assert_eq!(nonce(&data), "a");
+
+        // The other instance's answer is a duplicate
+        assert!(correlation.reply(reply(&nonces[0], Value::Null)).is_none());
+        assert!(correlation.requests.is_empty());
+    }
+
+    #[test]
+    fn error_is_held_until_every_instance_fails() {
+        let mut correlation = Correlation::default();
+        let nonces = correlation.begin(&command("a"), &instances());
+
+        assert!(correlation.reply(reply(&nonces[0], json!("ERROR"))).is_none());
+
+        let data = correlation.reply(reply(&nonces[1], json!("ERROR"))).unwrap();
+        assert_eq!(nonce(&data), "a");
+    }
+
+    #[test]
+    fn success_after_error_is_forwarded() {
+        let mut correlation = Correlation::default();
+        let nonces = correlation.begin(&command("a"), &instances());
+
+        assert!(correlation.reply(reply(&nonces[0], json!("ERROR"))).is_none());
+
+        let data = correlation.reply(reply(&nonces[1], Value::Null)).unwrap();
+        assert!(data.to_json_value::<Value>().unwrap()["evt"].is_null());
+    }
+
+    #[test]
+    fn internal_replies_are_dropped() {
+        let mut correlation = Correlation::default();
+        let nonces = correlation.begin_internal(&command("a"), &instances());
+
+        assert!(correlation.reply(reply(&nonces[0], Value::Null)).is_none());
+        assert!(correlation.reply(reply(&nonces[1], json!("ERROR"))).is_none());
+        assert!(correlation.requests.is_empty());
+    }
+
+    #[test]
+    fn untracked_frames_pass_through() {
+        let mut correlation = Correlation::default();
+        let data = reply("unknown", json!("READY"));
+
+        let data = correlation.reply(data).unwrap();
+        assert_eq!(nonce(&data), "unknown");
+    }
+
+    #[test]
+    fn expire_unanswered() {
+        let mut correlation = Correlation::default();
+        let nonces = correlation.begin(&command("a"), &instances());
+        assert!(correlation.reply(reply(&nonces[0], json!("ERROR"))).is_none());
+
+        assert!(correlation.expire(Duration::from_secs(60)).is_empty());
+
+        let expired = correlation.expire(Duration::ZERO);
+        assert_eq!(expired.len(), 1);
+        assert_eq!(nonce(&expired[0]), "a");
+        assert!(correlation.pending.is_empty());
+        assert!(correlation.requests.is_empty());
+    }
+
+    #[test]
+    fn fail_every_instance() {
+        let mut correlation = Correlation::default();
+        let nonces = correlation.begin(&command("a"), &instances());
+
+        assert!(correlation.fail(&nonces[0], "queue full").is_none());
+
+        let data = correlation.fail(&nonces[1], "queue full").unwrap();
+        let frame = data.to_json_value::<Value>().unwrap();
+        assert_eq!(frame["evt"], "ERROR");
+        assert_eq!(frame["data"]["message"], "queue full");
+    }
+}
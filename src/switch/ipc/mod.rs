@@ -1,9 +1,10 @@
-use std::{error::Error, path::PathBuf};
+use std::{collections::{HashMap, HashSet}, error::Error, fmt, path::PathBuf, sync::{Arc, Mutex, OnceLock}, time::{Duration, Instant}};
 
-use tokio::sync::{broadcast, mpsc::{self}};
+use serde_json::{Value, json};
+use tokio::{io::AsyncRead, sync::{mpsc::{self}, watch}};
 use tokio_util::sync::CancellationToken;
 
-use crate::{discord::{self, ipc::{Data, OpCode}}, switch::ipc::error::SwitchError};
+use crate::{config::{self, Config, error::ConfigError}, discord::{self, api::error::ApiError, ipc::{Data, OpCode, error::IpcError}, rpc}, switch::{affinity::Affinity, correlation::Correlation, ipc::error::SwitchError, limits::FrameRate, policy::{self, Admission, Route}, registry::{Event, Registry, SessionId}, schedule, throttle::{Admit, Throttle}, validate}};
 
 mod error;
 
@@ -13,32 +14,85 @@ mod unix;
 #[cfg(windows)]
 mod windows;
 
-const PREFERRED_NAME: &str = "discord-ipc-0";
+/// How often each client runs its periodic housekeeping
+const TICK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Synthetic pids start above the largest pid Linux can assign, so they never match a real process
+const SYNTHETIC_PID_BASE: u64 = 1 << 22;
 
 #[derive(Clone)]
 pub struct Server {
-    name: String,
+    /// IPC name chosen by `start`
+    name: Arc<OnceLock<String>>,
     token: CancellationToken,
+    source: config::Source,
+    config: Arc<watch::Sender<Arc<Config>>>,
+    profile: Arc<Mutex<Profile>>,
+    registry: Arc<Registry>,
+}
+
+/// The config as loaded and the profile applied on top of it
+struct Profile {
+    base: Arc<Config>,
+    active: Option<String>,
+    trigger: Trigger,
+}
+
+/// What activated the current profile
+#[derive(Clone, Debug, PartialEq)]
+pub enum Trigger {
+    /// The config's `profile`
+    Config,
+    /// A request on the control socket
+    Manual,
+    /// An `auto_profile` rule matching a running process
+    Process(String),
+}
+
+impl fmt::Display for Trigger {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Trigger::Config => write!(f, "config"),
+            Trigger::Manual => write!(f, "manual"),
+            Trigger::Process(name) => write!(f, "process {}", name),
+        }
+    }
+}
+
+impl Profile {
+    fn config(&self) -> Arc<Config> {
+        match self.active.as_deref().and_then(|name| self.base.with_profile(name)) {
+            Some(config) => Arc::new(config),
+            None => self.base.clone(),
+        }
+    }
 }
 
 impl Server {
-    pub fn new(token: CancellationToken) -> Result<Server, Box<dyn Error>> {
-        let name = discord::ipc::next_name()?;
-        tracing::info!("Creating switch IPC with name {}", name);
-        if name != PREFERRED_NAME {
-            // Most clients use the first IPC name, so warn the user if we couldn't use it
-            tracing::warn!("Warning: Preferred name {} is not available. Using {} instead.", PREFERRED_NAME, name);
-            tracing::warn!("Most RPC clients use the first available one, so desired behavior may not be achieved.");
-            tracing::warn!("Consider closing all Discord instances and running this program first to ensure the preferred name is used.");
+    pub fn new(token: CancellationToken, source: config::Source, config: Config) -> Server {
+        let profile = Profile {
+            active: config.profile.clone(),
+            base: Arc::new(config),
+            trigger: Trigger::Config,
+        };
+        if let Some(active) = &profile.active {
+            tracing::info!("Using profile {}", active);
         }
 
-        Ok(Server {
-            name,
+        Server {
+            name: Arc::new(OnceLock::new()),
             token,
-        })
+            source,
+            config: Arc::new(watch::Sender::new(profile.config())),
+            profile: Arc::new(Mutex::new(profile)),
+            registry: Arc::new(Registry::default()),
+        }
     }
 
     pub async fn start(self) -> Result<(), Box<dyn Error>> {
+        let name = self.choose_name().await?;
+        let _ = self.name.set(name);
+
         #[cfg(unix)]
         unix::start(self).await?;
 
@@ -48,38 +102,296 @@ impl Server {
         Ok(())
     }
 
+    /// Picks the preferred IPC name unless it is taken. A socket left behind there doesn't take
+    /// it, and is replaced when listening.
+    async fn choose_name(&self) -> Result<String, Box<dyn Error>> {
+        let preferred = self.config().server.name.clone();
+        let name = match discord::ipc::path(&preferred).exists() && !discord::ipc::stale(&preferred).await {
+            false => preferred.clone(),
+            true => discord::ipc::next_name()?,
+        };
+        tracing::info!("Creating switch IPC with name {}", name);
+        if name != preferred {
+            // Most clients use the first IPC name, so warn the user if we couldn't use it
+            tracing::warn!("Warning: Preferred name {} is not available. Using {} instead.", preferred, name);
+            tracing::warn!("Most RPC clients use the first available one, so desired behavior may not be achieved.");
+            tracing::warn!("Consider closing all Discord instances and running this program first to ensure the preferred name is used.");
+        }
+
+        Ok(name)
+    }
+
+    /// Our IPC name, or the preferred one until `start` has chosen
+    fn name(&self) -> String {
+        self.name.get().cloned().unwrap_or_else(|| self.config().server.name.clone())
+    }
+
     pub fn path(&self) -> PathBuf {
-        discord::ipc::path(&self.name)
+        discord::ipc::path(&self.name())
+    }
+
+    pub fn config(&self) -> Arc<Config> {
+        self.config.borrow().clone()
+    }
+
+    /// Watches for the config being replaced by `reload` or `set_profile`
+    pub fn subscribe(&self) -> watch::Receiver<Arc<Config>> {
+        self.config.subscribe()
+    }
+
+    /// Reloads the config from its source, keeping the current one if the new one is invalid.
+    ///
+    /// Routing, filters and arbitration apply to live sessions; the IPC name, sandbox and
+    /// application admission only apply from the next start or connection.
+    ///
+    /// The active profile is kept if the new config still defines it, and otherwise falls back
+    /// to the new config's `profile`.
+    pub fn reload(&self) -> Result<(), ConfigError> {
+        let config = self.source.load().inspect_err(|e| tracing::error!("Keeping current config: {}", e))?;
+
+        let mut profile = self.profile.lock().unwrap();
+        if let Some(active) = &profile.active
+            && !config.profiles.contains_key(active)
+        {
+            tracing::warn!("Profile {} is no longer defined", active);
+            profile.active = config.profile.clone();
+            profile.trigger = Trigger::Config;
+        }
+        profile.base = Arc::new(config);
+
+        self.config.send_replace(profile.config());
+        self.registry.reconcile_all();
+        tracing::info!("Config reloaded");
+
+        Ok(())
+    }
+
+    /// Name of the active profile and what activated it
+    pub fn profile(&self) -> (Option<String>, Trigger) {
+        let profile = self.profile.lock().unwrap();
+        (profile.active.clone(), profile.trigger.clone())
+    }
+
+    /// Names of the profiles the config defines
+    pub fn profiles(&self) -> Vec<String> {
+        let mut names = self.profile.lock().unwrap().base.profiles.keys().cloned().collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    /// Activates a profile, or the base config if `None`, and re-evaluates every session's
+    /// admission and activity under it. Returns false if the config defines no such profile.
+    pub fn set_profile(&self, name: Option<&str>, trigger: Trigger) -> bool {
+        let mut profile = self.profile.lock().unwrap();
+        if let Some(name) = name
+            && !profile.base.profiles.contains_key(name)
+        {
+            return false;
+        }
+        if profile.active.as_deref() == name {
+            profile.trigger = trigger;
+            return true;
+        }
+
+        tracing::info!(
+            "Switching profile from {} to {} ({})",
+            profile.active.as_deref().unwrap_or("(none)"),
+            name.unwrap_or("(none)"),
+            trigger
+        );
+        profile.active = name.map(str::to_owned);
+        profile.trigger = trigger;
+
+        self.config.send_replace(profile.config());
+        self.registry.readmit_all();
+        self.registry.reconcile_all();
+
+        true
+    }
+
+    pub fn token(&self) -> &CancellationToken {
+        &self.token
+    }
+
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    /// Registers a session for a new client, or `None` if that would exceed the session limit
+    pub fn reserve(&self, events: mpsc::UnboundedSender<Event>) -> Option<SessionId> {
+        let limit = self.config().limits.sessions;
+        let session = self.registry.register(events, limit);
+        if session.is_none() {
+            tracing::warn!("Rejecting client, {} sessions are already connected", limit);
+        }
+
+        session
+    }
+
+    /// Checks the connecting process belongs to a user allowed to drive our Discord accounts
+    #[cfg(unix)]
+    pub fn trusted(&self, stream: &tokio::net::UnixStream) -> bool {
+        let cred = match stream.peer_cred() {
+            Ok(cred) => cred,
+            Err(e) => {
+                tracing::error!("Rejecting client, unable to read its credentials: {}", e);
+                return false;
+            }
+        };
+
+        // SAFETY: getuid is always successful
+        let own_uid = unsafe { libc::getuid() };
+        let peer = &self.config().peer;
+        // Supplementary groups are only looked up when groups are allowed
+        let groups = if peer.gids.is_empty() {
+            vec![cred.gid()]
+        } else {
+            crate::process::groups(cred.uid(), cred.gid())
+        };
+        if policy::trust(peer, own_uid, cred.uid(), &groups) {
+            return true;
+        }
+
+        tracing::warn!(
+            "Rejecting client from uid {} gid {} (pid {:?})",
+            cred.uid(),
+            cred.gid(),
+            cred.pid()
+        );
+        false
+    }
+
+    /// Clears and withholds activity on an instance, for `duration` if given
+    pub fn mute(&self, instance: &str, duration: Option<Duration>) {
+        // Too long a duration to represent lasts until unmuted
+        let until = duration.and_then(|duration| Instant::now().checked_add(duration));
+        self.registry.mute(instance, until);
+
+        match duration {
+            Some(duration) => tracing::info!("Muted {} for {:?}", instance, duration),
+            None => tracing::info!("Muted {}", instance),
+        }
+
+        if let Some(until) = until {
+            let registry = self.registry.clone();
+            let instance = instance.to_owned();
+            tokio::spawn(async move {
+                tokio::time::sleep_until(until.into()).await;
+                registry.expire_mute(&instance, until);
+            });
+        }
     }
 
     /// Gets names of IPCs that excludes our own
     pub fn other_ipc_names(&self) -> Vec<String> {
-        let names = discord::ipc::names();
-        names.into_iter().filter(|name| *name != self.name).collect::<Vec<_>>()
+        let own = self.name();
+        let names = discord::ipc::names(&self.config().server.ipc_dirs);
+        names.into_iter().filter(|name| *name != own).collect::<Vec<_>>()
+    }
+}
+
+/// Reads frames from an RPC client until it disconnects or exceeds a limit, in which case it is
+/// sent a Close frame
+async fn read_frames<R: AsyncRead + Unpin>(
+    mut reader: R,
+    limits: config::Limits,
+    frames_tx: mpsc::UnboundedSender<Data>,
+    switch_tx: mpsc::UnboundedSender<Data>,
+) {
+    let mut rate = FrameRate::new(limits.frames_per_second);
+
+    loop {
+        let data = match discord::ipc::read(&mut reader, limits.frame_size).await {
+            Ok(Some(data)) => data,
+            Ok(None) => break,
+            Err(e) => {
+                if let Some(IpcError::FrameTooLarge(_)) = e.get_ref().and_then(|e| e.downcast_ref()) {
+                    tracing::warn!("Closing client: {} (limit is {} bytes)", e, limits.frame_size);
+                    let _ = switch_tx.send(rpc::close(rpc::CLOSE_TOO_LARGE, "Frame too large"));
+                } else {
+                    tracing::error!("Error reading from client: {}", e);
+                }
+                break;
+            }
+        };
+
+        if !rate.allow() {
+            tracing::warn!("Closing client: sent more than {} frames per second", limits.frames_per_second);
+            let _ = switch_tx.send(rpc::close(rpc::CLOSE_RATE_LIMITED, "Rate limited"));
+            break;
+        }
+
+        tracing::trace!("Switch Reader: {}", data.msg);
+
+        if frames_tx.send(data).is_err() {
+            // Client is closed, closing reader
+            break;
+        }
     }
 }
 
 pub struct Client {
     server: Server,
+    session: SessionId,
     handshake: Option<discord::api::Handshake>,
     app_data: Option<discord::api::ApplicationRpcData>,
     switch_tx: mpsc::UnboundedSender<Data>,
+    upstream_tx: mpsc::UnboundedSender<(String, Data)>,
     discord_ipc_clients: Vec<discord::ipc::Client>,
-    discord_channel: (broadcast::Sender<Data>, broadcast::Receiver<Data>),
+    affinity: Affinity,
+    correlation: Correlation,
+    /// Activity the client last set
+    activity: Option<Value>,
+    /// `pid` the client last set its activity with
+    pid: Value,
+    /// When the client last sent a frame, which its idle timeout is measured from
+    last_frame: Instant,
+    /// Process connected to the switch, from the socket's credentials
+    peer_pid: Option<i32>,
+    /// Names of the application's art assets, if checking image keys and they could be fetched
+    assets: Option<HashSet<String>>,
+    /// Unknown image keys already warned about
+    unknown_assets: HashSet<String>,
+    /// Activity currently shown on each instance
+    shown: HashMap<String, Value>,
+    throttle: Throttle,
+    events_tx: mpsc::UnboundedSender<Event>,
+    swallowed: bool,
     closed: bool,
 }
 
 impl Client {
-    pub fn new(server: Server, tx: mpsc::UnboundedSender<Data>) -> Client {
-        let discord_channel = broadcast::channel::<Data>(16);
+    /// Creates the client of a session reserved with `Server::reserve`
+    pub fn new(
+        server: Server,
+        session: SessionId,
+        tx: mpsc::UnboundedSender<Data>,
+        upstream_tx: mpsc::UnboundedSender<(String, Data)>,
+        events_tx: mpsc::UnboundedSender<Event>,
+        peer_pid: Option<i32>,
+    ) -> Client {
+        server.registry().update(session, |session| session.pid = peer_pid);
 
         Client {
             server,
+            session,
             handshake: None,
             app_data: None,
             switch_tx: tx,
+            upstream_tx,
             discord_ipc_clients: vec![],
-            discord_channel,
+            affinity: Affinity::default(),
+            correlation: Correlation::default(),
+            activity: None,
+            pid: Value::Null,
+            last_frame: Instant::now(),
+            peer_pid,
+            assets: None,
+            unknown_assets: HashSet::new(),
+            shown: HashMap::new(),
+            throttle: Throttle::default(),
+            events_tx,
+            swallowed: false,
             closed: false,
         }
     }
@@ -106,6 +418,7 @@ impl Client {
 
     pub async fn handle(&mut self, data: Data) -> Result<(), Box<dyn Error>> {
         tracing::trace!("Handling {}", data.msg);
+        self.last_frame = Instant::now();
 
         match data.opcode {
             OpCode::Handshake => self.handshake(data).await?,
@@ -117,18 +430,102 @@ impl Client {
         Ok(())
     }
 
+    /// Handles a frame read from one of the Discord instances
+    pub async fn handle_upstream(&mut self, name: &str, data: Data) -> Result<(), Box<dyn Error>> {
+        if let OpCode::Frame = data.opcode
+            && let Ok(frame) = data.to_json_value::<Value>()
+        {
+            self.affinity.record(name, &frame);
+        }
+
+        // Discord IPC clients -> Switch Client
+        if let Some(data) = self.correlation.reply(data) {
+            self.switch_tx.send(data)?;
+        }
+
+        Ok(())
+    }
+
+    /// Handles an event sent by the rest of the server
+    pub async fn handle_event(&mut self, event: Event) -> Result<(), Box<dyn Error>> {
+        match event {
+            Event::Reconcile => self.reconcile(),
+            Event::Kick => {
+                tracing::info!("{} client kicked", self.id());
+                self.switch_tx.send(rpc::close(rpc::CLOSE_NORMAL, "Kicked"))?;
+                self.closed = true;
+                Ok(())
+            }
+            Event::Readmit => self.readmit().await,
+            Event::Application(data) => {
+                tracing::info!("{} client is {}", self.id(), data.name);
+                let name = data.name.clone();
+                self.server.registry().update(self.session, |session| session.name = Some(name));
+                self.app_data = Some(data);
+                Ok(())
+            }
+            Event::Assets(assets) => {
+                self.assets = Some(assets);
+                Ok(())
+            }
+        }
+    }
+
+    /// Tears down the session of a client whose process exited without closing its socket,
+    /// such as one killed while another process holds the socket open
+    pub fn process_exited(&mut self) -> Result<(), Box<dyn Error>> {
+        tracing::info!("{} client process exited, closing its session", self.id());
+        self.clear_activity()?;
+        self.closed = true;
+
+        Ok(())
+    }
+
+    /// Runs periodic housekeeping, called every `TICK_INTERVAL`
+    pub fn tick(&mut self) -> Result<(), Box<dyn Error>> {
+        let config = self.server.config();
+        let timeout = Duration::from_secs(config.commands.timeout);
+        for data in self.correlation.expire(timeout) {
+            self.switch_tx.send(data)?;
+        }
+
+        // Clear the activity of a client that stopped talking without disconnecting, until it sets one again
+        let client_id = self.handshake.as_ref().map(|handshake| handshake.client_id.as_str()).unwrap_or_default();
+        if self.activity.is_some()
+            && let Some(idle_timeout) = config.activity.idle_timeout(client_id)
+            && self.last_frame.elapsed() >= idle_timeout
+        {
+            tracing::info!("{} client idle for {:?}, clearing its activity", self.id(), idle_timeout);
+            self.clear_activity()?;
+        }
+
+        Ok(())
+    }
+
     async fn handshake(&mut self, data: Data) -> Result<(), Box<dyn Error>> {
         let handshake: discord::api::Handshake = data.to_json_value()?;
         let client_id = handshake.client_id.clone();
         self.handshake = Some(handshake);
+        self.server.registry().identify(self.session, &client_id);
+
+        match policy::admit(&self.server.config().applications, &client_id) {
+            Admission::Relay => {}
+            Admission::Block => {
+                tracing::info!("{} client blocked", client_id);
+                self.switch_tx.send(rpc::close(rpc::CLOSE_INVALID_CLIENT_ID, "Invalid Client ID"))?;
+                self.closed = true;
+                return Ok(());
+            }
+            Admission::Swallow => {
+                tracing::info!("{} client swallowed", client_id);
+                self.switch_tx.send(rpc::ready())?;
+                self.swallowed = true;
+                return Ok(());
+            }
+        }
 
-        self.app_data = match crate::discord::api::application_rpc(&client_id).await {
-            Ok(data) => Some(data),
-            Err(e) => {
-                tracing::error!("Unable to retrieve client application metadata from Discord: {}", e);
-                None
-            },
-        };
+        // Don't hold up the handshake on the Discord API, the session is labelled once it answers
+        self.look_up(client_id);
 
         self.connected();
         self.setup_discord_ipc_clients().await?;
@@ -136,6 +533,72 @@ impl Client {
         Ok(())
     }
 
+    /// Applies the current `applications` admission to a session that has already handshaken. Blocked
+    /// sessions are closed, and swallowed ones have their activity cleared.
+    async fn readmit(&mut self) -> Result<(), Box<dyn Error>> {
+        let Some(client_id) = self.handshake.as_ref().map(|handshake| handshake.client_id.clone()) else {
+            return Ok(());
+        };
+
+        match (policy::admit(&self.server.config().applications, &client_id), self.swallowed) {
+            (Admission::Relay, false) | (Admission::Swallow, true) => {}
+            (Admission::Block, _) => {
+                tracing::info!("{} client blocked", self.id());
+                self.clear_activity()?;
+                self.switch_tx.send(rpc::close(rpc::CLOSE_INVALID_CLIENT_ID, "Invalid Client ID"))?;
+                self.closed = true;
+            }
+            (Admission::Swallow, false) => {
+                tracing::info!("{} client swallowed", self.id());
+                self.clear_activity()?;
+                self.swallowed = true;
+            }
+            (Admission::Relay, true) => {
+                tracing::info!("{} client no longer swallowed", client_id);
+                self.swallowed = false;
+                self.look_up(client_id);
+                self.setup_discord_ipc_clients().await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Looks up the application's metadata and assets in the background, sending each to the
+    /// session as an event when found
+    fn look_up(&self, client_id: String) {
+        let config = self.server.config();
+        let events_tx = self.events_tx.clone();
+
+        tokio::spawn(async move {
+            let application = async {
+                match discord::api::retry(&config.api, || discord::api::application_rpc(&config, &client_id)).await {
+                    Ok(data) => {
+                        let _ = events_tx.send(Event::Application(data));
+                    }
+                    Err(ApiError::Offline) => tracing::debug!("No metadata of application {} cached while offline", client_id),
+                    Err(e) => tracing::warn!("Unable to retrieve metadata of application {} from Discord: {}", client_id, e),
+                }
+            };
+
+            let assets = async {
+                if config.activity.assets.unknown == config::UnknownAssets::Off {
+                    return;
+                }
+                match discord::api::retry(&config.api, || discord::api::application_assets(&config, &client_id)).await {
+                    Ok(assets) => {
+                        let names = assets.into_iter().map(|asset| asset.name.to_lowercase()).collect();
+                        let _ = events_tx.send(Event::Assets(names));
+                    }
+                    Err(ApiError::Offline) => tracing::debug!("No assets of application {} cached while offline", client_id),
+                    Err(e) => tracing::warn!("Unable to retrieve assets of application {} from Discord: {}", client_id, e),
+                }
+            };
+
+            tokio::join!(application, assets);
+        });
+    }
+
     async fn ping(&mut self) -> Result<(), Box<dyn Error>> {
         // Respond with pong
         let pong = Data {
@@ -155,10 +618,289 @@ impl Client {
 
     async fn relay(&mut self, data: Data) -> Result<(), Box<dyn Error>> {
         //! Relay message to connected Discord clients
+        let command: rpc::Command = match data.to_json_value() {
+            Ok(command) => command,
+            Err(e) => {
+                tracing::warn!("[Client: {}] Invalid command: {}", self.id(), e);
+                self.switch_tx.send(rpc::error("", &Value::Null, rpc::ERROR_INVALID_PAYLOAD, "Invalid payload"))?;
+                return Ok(());
+            }
+        };
+
+        if self.swallowed {
+            // Acknowledge locally so the client believes Discord accepted it, which echoes activity back
+            let data = match command.cmd.as_str() {
+                "SET_ACTIVITY" => command.rest.get("args").and_then(|args| args.get("activity")).cloned().unwrap_or_default(),
+                _ => Value::Null,
+            };
+            self.switch_tx.send(rpc::ack(&command.cmd, &command.nonce, data))?;
+            return Ok(());
+        }
+
         // Switch Client -> Discord IPC clients
-        tracing::trace!("Switch -> Discord: {}", data.msg);
-        let discord_tx = self.discord_channel.0.clone();
-        discord_tx.send(data)?;
+        let client_id = self.handshake.as_ref().map(|handshake| handshake.client_id.as_str()).unwrap_or_default();
+        let route = match policy::route(&self.server.config(), client_id, &command.cmd) {
+            // Replies to an event only go back to the instance that dispatched it
+            Route::All => {
+                let args = command.rest.get("args").unwrap_or(&Value::Null);
+                self.affinity.take(&command.cmd, args).map_or(Route::All, Route::Instance)
+            }
+            route => route,
+        };
+
+        let mut instances = match route {
+            Route::All => self.discord_ipc_clients.iter().map(|client| client.name.clone()).collect(),
+            Route::Instance(name) if self.discord_ipc_clients.iter().any(|client| client.name == name) => vec![name],
+            Route::Instance(name) => {
+                let message = format!("{} is not available to handle {}", name, command.cmd);
+                return self.deny(&command, &message);
+            }
+            Route::Deny => {
+                let message = format!("{} is not permitted by {}", command.cmd, env!("CARGO_PKG_NAME"));
+                return self.deny(&command, &message);
+            }
+        };
+
+        if command.cmd == "SET_ACTIVITY" {
+            // Disabled instances are left out when deciding what to show instead
+            return self.set_activity(command, instances);
+        }
+
+        instances.retain(|name| self.server.registry().enabled(name));
+        if instances.is_empty() {
+            let message = format!("No enabled Discord instance to handle {}", command.cmd);
+            return self.deny(&command, &message);
+        }
+
+        self.forward(command, &instances)
+    }
+
+    /// Records the client's activity and shows it on the instances this session wins
+    fn set_activity(&mut self, mut command: rpc::Command, instances: Vec<String>) -> Result<(), Box<dyn Error>> {
+        let validation = self.server.config().activity.validation;
+        if validation != config::Validation::Off
+            && let Some(activity) = command.rest.get_mut("args").and_then(|args| args.get_mut("activity"))
+        {
+            let fix = validation == config::Validation::Fix;
+            match validate::activity(activity, fix) {
+                Ok(problems) if problems.is_empty() => {}
+                Ok(problems) if fix => tracing::info!("[Client: {}] Fixed activity: {}", self.id(), problems.join("; ")),
+                Ok(problems) => return self.invalid(&command, &problems.join("; ")),
+                Err(problem) => return self.invalid(&command, &problem),
+            }
+        }
+
+        self.check_assets(&mut command);
+
+        let args = command.rest.get("args").cloned().unwrap_or_default();
+        self.pid = args["pid"].clone();
+        self.activity = Some(args["activity"].clone()).filter(|activity| !activity.is_null());
+
+        self.server.registry().set_activity(self.session, self.activity.clone());
+
+        let relayed = self.update(Some(&command), &instances)?;
+        if !relayed {
+            // Held, unchanged or not shown anywhere, but the client still expects an answer
+            let activity = self.activity.clone().unwrap_or_default();
+            self.switch_tx.send(rpc::ack(&command.cmd, &command.nonce, activity))?;
+        }
+
+        Ok(())
+    }
+
+    /// Warns about, strips or replaces image keys the application has no asset for
+    fn check_assets(&mut self, command: &mut rpc::Command) {
+        let config = self.server.config();
+        let check = &config.activity.assets;
+        let Some(known) = &self.assets else {
+            return;
+        };
+        let Some(activity) = command.rest.get_mut("args").and_then(|args| args.get_mut("activity")) else {
+            return;
+        };
+
+        for (field, key) in validate::unknown_assets(activity, known) {
+            if self.unknown_assets.insert(key.clone()) {
+                tracing::warn!("[Client: {}] Application has no asset {:?} for {}", self.id(), key, field);
+            }
+
+            let Some(assets) = activity.get_mut("assets").and_then(Value::as_object_mut) else {
+                continue;
+            };
+            match (check.unknown, &check.replacement) {
+                (config::UnknownAssets::Strip, _) => {
+                    assets.remove(field);
+                }
+                (config::UnknownAssets::Replace, Some(replacement)) => {
+                    assets.insert(field.to_owned(), Value::String(replacement.clone()));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Activity this session should show on an instance
+    fn desired(&self, config: &Config, name: &str) -> Option<Value> {
+        let activity = self.activity.as_ref()?;
+        if config.mute.iter().any(|muted| muted == name) || schedule::instance_quiet(config, name, schedule::now()) {
+            return None;
+        }
+        if !self.server.registry().enabled(name) || self.server.registry().muted(name) {
+            return None;
+        }
+
+        let winner = self.server.registry().winner(config, name);
+
+        (winner == Some(self.session)).then(|| activity.clone())
+    }
+
+    /// Forgets the client's activity and clears it wherever this session shows it
+    fn clear_activity(&mut self) -> Result<(), Box<dyn Error>> {
+        self.activity = None;
+        self.server.registry().set_activity(self.session, None);
+        self.reconcile()
+    }
+
+    /// Updates every instance whose shown activity differs from what this session should show
+    fn reconcile(&mut self) -> Result<(), Box<dyn Error>> {
+        self.update(None, &[])?;
+
+        Ok(())
+    }
+
+    /// Sends an activity update to every instance whose shown activity differs from what it should
+    /// be. The client's `command` is relayed to the `instances` it was routed to when it describes
+    /// the update; otherwise the switch sends its own. Returns whether `command` was relayed.
+    fn update(&mut self, command: Option<&rpc::Command>, instances: &[String]) -> Result<bool, Box<dyn Error>> {
+        let config = self.server.config();
+        let window = Duration::from_secs(config.activity.window);
+        let names = self.discord_ipc_clients.iter().map(|client| client.name.clone()).collect::<Vec<_>>();
+        let mut relay = Vec::new();
+
+        for name in names {
+            let desired = self.desired(&config, &name);
+            if self.shown.get(&name) == desired.as_ref() {
+                self.throttle.release(&name);
+                continue;
+            }
+
+            match self.throttle.admit(&name, window) {
+                Admit::Now => {}
+                Admit::Schedule(wait) => {
+                    tracing::debug!("[Client: {}] Holding activity for {} for {:?}", self.id(), name, wait);
+                    let events_tx = self.events_tx.clone();
+                    tokio::spawn(async move {
+                        tokio::time::sleep(wait).await;
+                        let _ = events_tx.send(Event::Reconcile);
+                    });
+                    continue;
+                }
+                Admit::Hold => continue,
+            }
+
+            match desired.clone() {
+                Some(activity) => self.shown.insert(name.clone(), activity),
+                None => self.shown.remove(&name),
+            };
+
+            if command.is_some() && desired == self.activity && instances.contains(&name) {
+                relay.push(name);
+                continue;
+            }
+
+            tracing::debug!("[Client: {}] {} activity on {}", self.id(), if desired.is_some() { "Showing" } else { "Clearing" }, name);
+
+            let pid = match self.pid {
+                Value::Null => json!(std::process::id()),
+                ref pid => pid.clone(),
+            };
+            let command: rpc::Command = serde_json::from_value(json!({
+                "cmd": "SET_ACTIVITY",
+                "args": { "pid": pid, "activity": desired },
+            }))?;
+
+            let instances = [name];
+            let nonces = self.correlation.begin_internal(&command, &instances);
+            self.send_each(command, &instances, nonces)?;
+        }
+
+        match command {
+            Some(command) if !relay.is_empty() => {
+                self.forward(command.clone(), &relay)?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// Sends a command to each instance under its own nonce
+    fn forward(&mut self, command: rpc::Command, instances: &[String]) -> Result<(), Box<dyn Error>> {
+        let nonces = self.correlation.begin(&command, instances);
+        self.send_each(command, instances, nonces)
+    }
+
+    fn send_each(&mut self, mut command: rpc::Command, instances: &[String], nonces: Vec<String>) -> Result<(), Box<dyn Error>> {
+        let config = self.server.config();
+        let pid = match command.cmd.as_str() {
+            "SET_ACTIVITY" => command.rest.get("args").and_then(|args| args.get("pid")).cloned(),
+            _ => None,
+        };
+
+        for (name, nonce) in instances.iter().zip(nonces) {
+            let Some(client) = self.discord_ipc_clients.iter().find(|client| client.name == *name) else {
+                continue;
+            };
+
+            command.nonce = Value::String(nonce.clone());
+            if let Some(pid) = &pid
+                && let Some(args) = command.rest.get_mut("args").and_then(Value::as_object_mut)
+            {
+                args.insert(String::from("pid"), self.relay_pid(&config, name, pid));
+            }
+            let data = Data {
+                opcode: OpCode::Frame,
+                msg: serde_json::to_string(&command)?,
+            };
+            tracing::trace!("Switch -> Discord {}: {}", name, data.msg);
+
+            if let Err(e) = client.send(data) {
+                let message = format!("Unable to send to {}: {}", name, e);
+                tracing::error!("[Client: {}] {}", self.id(), message);
+                if let Some(data) = self.correlation.fail(&nonce, &message) {
+                    self.switch_tx.send(data)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// `pid` to relay the client's activity to an instance with
+    fn relay_pid(&self, config: &Config, instance: &str, pid: &Value) -> Value {
+        match config.pid(instance) {
+            config::Pid::Client => pid.clone(),
+            config::Pid::Peer => self.peer_pid.map_or_else(|| pid.clone(), |pid| json!(pid)),
+            config::Pid::Session => json!(SYNTHETIC_PID_BASE + self.session),
+        }
+    }
+
+    fn send(&self, client: &discord::ipc::Client, data: Data) {
+        if let Err(e) = client.send(data) {
+            tracing::error!("[Client: {}] Unable to send to {}: {}", self.id(), client.name, e);
+        }
+    }
+
+    fn deny(&self, command: &rpc::Command, message: &str) -> Result<(), Box<dyn Error>> {
+        tracing::warn!("[Client: {}] Denied {}: {}", self.id(), command.cmd, message);
+        self.switch_tx.send(rpc::error(&command.cmd, &command.nonce, rpc::ERROR_INVALID_PERMISSIONS, message))?;
+
+        Ok(())
+    }
+
+    /// Answers a command Discord would reject without relaying it
+    fn invalid(&self, command: &rpc::Command, message: &str) -> Result<(), Box<dyn Error>> {
+        tracing::warn!("[Client: {}] Rejected {}: {}", self.id(), command.cmd, message);
+        self.switch_tx.send(rpc::error(&command.cmd, &command.nonce, rpc::ERROR_INVALID_PAYLOAD, message))?;
 
         Ok(())
     }
@@ -168,9 +910,9 @@ impl Client {
         let mut clients = Vec::new();
 
         for name in ipc_names {
-            let client = discord::ipc::Client::new(&name, &self.discord_channel, self.switch_tx.clone());
+            let mut client = discord::ipc::Client::new(&name, self.upstream_tx.clone());
 
-            if let Err(e) = client.connect().await {
+            if let Err(e) = client.connect(self.server.config().server.queue_capacity).await {
                 tracing::error!("[Client: {}] Failed to connect to {}: {}", self.id(), client.name, e);
                 continue;
             }
@@ -182,16 +924,19 @@ impl Client {
             return Err(Box::new(SwitchError::NoDiscords));
         }
 
+        let upstreams = clients.iter().map(|client| client.name.clone()).collect();
+        self.server.registry().update(self.session, |session| session.upstreams = upstreams);
         self.discord_ipc_clients = clients;
 
         // Send handshake to Discord clients
         if let Some(handshake) = self.handshake.as_ref() {
-            let discord_tx = self.discord_channel.0.clone();
             let data = Data {
                 opcode: OpCode::Handshake,
                 msg: serde_json::to_string(handshake)?
             };
-            discord_tx.send(data)?;
+            for client in self.discord_ipc_clients.iter() {
+                self.send(client, data.clone());
+            }
         } else {
             tracing::warn!("Missing handshake data");
         }
@@ -199,3 +944,71 @@ impl Client {
         Ok(())
     }
 }
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        self.server.registry().unregister(self.session);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A client that has handshaken as `client_id`, without any Discord instances
+    fn client(server: &Server, client_id: &str) -> (Client, mpsc::UnboundedReceiver<Data>, mpsc::UnboundedReceiver<Event>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let (upstream_tx, _) = mpsc::unbounded_channel();
+        let (events_tx, events_rx) = mpsc::unbounded_channel();
+
+        let session = server.reserve(events_tx.clone()).unwrap();
+        let mut client = Client::new(server.clone(), session, tx, upstream_tx, events_tx, None);
+        client.handshake = Some(discord::api::Handshake { v: 1, client_id: client_id.to_owned() });
+        server.registry().identify(client.session, client_id);
+        client.activity = Some(json!({ "state": "Playing" }));
+        server.registry().set_activity(client.session, client.activity.clone());
+
+        (client, rx, events_rx)
+    }
+
+    #[tokio::test]
+    async fn readmit_on_profile_switch() {
+        let config = Config::parse("[profiles.streaming.applications]\nblock = [\"123\"]\nswallow = [\"456\"]").unwrap();
+        let server = Server::new(CancellationToken::new(), config::Source::default(), config);
+        let (mut blocked, mut blocked_rx, mut blocked_events) = client(&server, "123");
+        let (mut swallowed, _, mut swallowed_events) = client(&server, "456");
+
+        assert!(server.set_profile(Some("streaming"), Trigger::Manual));
+
+        while let Ok(event) = blocked_events.try_recv() {
+            blocked.handle_event(event).await.unwrap();
+        }
+        assert!(blocked.closed);
+        assert!(matches!(blocked_rx.try_recv().unwrap().opcode, OpCode::Close));
+
+        while let Ok(event) = swallowed_events.try_recv() {
+            swallowed.handle_event(event).await.unwrap();
+        }
+        assert!(swallowed.swallowed);
+        assert!(!swallowed.closed);
+        assert!(swallowed.activity.is_none());
+        assert!(server.registry().list().iter().all(|(_, session)| session.activity.is_none()));
+    }
+
+    #[tokio::test]
+    async fn swallowed_activity_is_echoed() {
+        let config = Config::parse("[applications]\nswallow = [\"456\"]").unwrap();
+        let server = Server::new(CancellationToken::new(), config::Source::default(), config);
+        let (mut client, mut rx, _) = client(&server, "456");
+        client.activity = None;
+        client.swallowed = true;
+
+        let frame = json!({ "cmd": "SET_ACTIVITY", "nonce": "1", "args": { "pid": 1, "activity": { "state": "Playing" } } });
+        client.handle(Data { opcode: OpCode::Frame, msg: frame.to_string() }).await.unwrap();
+
+        let reply: Value = rx.try_recv().unwrap().to_json_value().unwrap();
+        assert_eq!(reply["nonce"], "1");
+        assert_eq!(reply["data"], json!({ "state": "Playing" }));
+        assert!(client.activity.is_none());
+    }
+}
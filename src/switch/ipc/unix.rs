@@ -1,18 +1,27 @@
 use std::error::Error;
 
 use tokio::{
-    fs, io::{self, AsyncReadExt, AsyncWriteExt}, net::{UnixListener, UnixStream}, sync::mpsc
+    fs, io::{self, AsyncWriteExt}, net::UnixStream, sync::mpsc
 };
-use tokio_util::bytes::BytesMut;
 
-use crate::{discord::ipc::{Data, OpCode}, switch::ipc::{Client, Server}};
+use crate::{discord::{self, ipc::Data, rpc}, process, switch::{ipc::{Client, Server, TICK_INTERVAL, read_frames}, registry::Event}};
 
 async fn handle(server: Server, stream: UnixStream) -> Result<(), Box<dyn Error>> {
     tracing::info!("Received new client");
 
-    let (mut read_half, mut write_half) = stream.into_split();
+    let (events_tx, mut events_rx) = mpsc::unbounded_channel::<Event>();
+    let Some(session) = server.reserve(events_tx.clone()) else {
+        let mut stream = stream;
+        let mut buf = rpc::close(rpc::CLOSE_RATE_LIMITED, "Too many sessions").to_buf();
+        stream.write_all_buf(&mut buf).await?;
+        return Ok(());
+    };
+
+    let peer_pid = stream.peer_cred().ok().and_then(|cred| cred.pid());
+    let (read_half, mut write_half) = stream.into_split();
     let (tx, mut rx) = mpsc::unbounded_channel::<Data>();
-    let mut client = Client::new(server, tx);
+    let (upstream_tx, mut upstream_rx) = mpsc::unbounded_channel::<(String, Data)>();
+    let mut client = Client::new(server.clone(), session, tx.clone(), upstream_tx, events_tx, peer_pid);
 
     // Writer
     tokio::spawn(async move {
@@ -37,36 +46,42 @@ async fn handle(server: Server, stream: UnixStream) -> Result<(), Box<dyn Error>
     });
 
     // Reader
+    let (frames_tx, mut frames_rx) = mpsc::unbounded_channel::<Data>();
+    let limits = server.config().limits.clone();
+    tokio::spawn(read_frames(read_half, limits, frames_tx, tx));
+
+    let mut ticker = tokio::time::interval(TICK_INTERVAL);
+
+    // The socket can outlive the client's process, so watch the process itself
+    let exited = async {
+        match peer_pid {
+            Some(pid) => process::exited(pid).await,
+            None => std::future::pending().await,
+        }
+    };
+    tokio::pin!(exited);
+
     loop {
         if client.closed {
             break;
         }
 
-        read_half.readable().await?;
+        tokio::select! {
+            data = frames_rx.recv() => match data {
+                Some(data) => client.handle(data).await?,
+                None => break,
+            },
 
-        // Expect a u32 indicating opcode
-        let opcode = OpCode::from_u32(read_half.read_u32_le().await?)?;
+            Some((name, data)) = upstream_rx.recv() => {
+                client.handle_upstream(&name, data).await?;
+            }
 
-        // Expect a u32 indicating length
-        let length = read_half.read_u32_le().await? as usize;
+            Some(event) = events_rx.recv() => client.handle_event(event).await?,
 
-        // Read the message
-        let mut buf = BytesMut::with_capacity(length);
-        let n = read_half.read_buf(&mut buf).await?;
+            _ = ticker.tick() => client.tick()?,
 
-        if n == 0 {
-            break;
+            _ = &mut exited => client.process_exited()?,
         }
-
-        let msg = String::from_utf8(buf.to_vec())?;
-        tracing::trace!("Switch Reader: {}", msg);
-
-        let data = Data {
-            opcode,
-            msg
-        };
-
-        client.handle(data).await?;
     }
 
     client.disconnected();
@@ -76,24 +91,8 @@ async fn handle(server: Server, stream: UnixStream) -> Result<(), Box<dyn Error>
 
 pub async fn start(server: Server) -> Result<(), Box<dyn Error>> {
     let path = server.path();
-    let listener = loop {
-        match UnixListener::bind(&path) {
-            Ok(listener) => break listener,
-            Err(e) if e.kind() == io::ErrorKind::AddrInUse => {
-                // Verify the socket path is dead before replacing it
-                match UnixStream::connect(&path).await {
-                    Ok(_) => return Err(Box::new(e)),
-                    Err(_) => {
-                        if let Err(e) = fs::remove_file(&path).await {
-                            return Err(Box::new(e));
-                        }
-                        continue;
-                    }
-                }
-            }
-            Err(e) => return Err(Box::new(e)),
-        }
-    };
+    // Peers are also checked on accept
+    let listener = discord::ipc::listen(&path, &server.config().peer).await?;
     tracing::info!("Server listening for clients");
 
     loop {
@@ -103,6 +102,10 @@ pub async fn start(server: Server) -> Result<(), Box<dyn Error>> {
             result = listener.accept() => {
                 match result {
                     Ok((stream, _)) => {
+                        if !server.trusted(&stream) {
+                            continue;
+                        }
+
                         tokio::spawn(async move {
                             if let Err(e) = handle(server, stream).await {
                                 tracing::error!("Error handling client: {}", e);
@@ -1,20 +1,28 @@
 use std::error::Error;
 
 use tokio::{
-    io::{self, AsyncReadExt, AsyncWriteExt},
+    io::{self, AsyncWriteExt},
     net::windows::named_pipe::ServerOptions,
     sync::mpsc,
 };
-use tokio_util::bytes::BytesMut;
 
-use crate::{discord::ipc::{Data, OpCode}, switch::ipc::{Client, Server}};
+use crate::{discord::{ipc::Data, rpc}, switch::{ipc::{Client, Server, TICK_INTERVAL, read_frames}, registry::Event}};
 
 async fn handle(server: Server, pipe: tokio::net::windows::named_pipe::NamedPipeServer) -> Result<(), Box<dyn Error>> {
     tracing::info!("Received new client");
 
-    let (mut read_half, mut write_half) = tokio::io::split(pipe);
+    let (events_tx, mut events_rx) = mpsc::unbounded_channel::<Event>();
+    let Some(session) = server.reserve(events_tx.clone()) else {
+        let mut pipe = pipe;
+        let mut buf = rpc::close(rpc::CLOSE_RATE_LIMITED, "Too many sessions").to_buf();
+        pipe.write_all_buf(&mut buf).await?;
+        return Ok(());
+    };
+
+    let (read_half, mut write_half) = tokio::io::split(pipe);
     let (tx, mut rx) = mpsc::unbounded_channel::<Data>();
-    let mut client = Client::new(server, tx);
+    let (upstream_tx, mut upstream_rx) = mpsc::unbounded_channel::<(String, Data)>();
+    let mut client = Client::new(server.clone(), session, tx.clone(), upstream_tx, events_tx, None);
 
     // Writer
     tokio::spawn(async move {
@@ -37,34 +45,31 @@ async fn handle(server: Server, pipe: tokio::net::windows::named_pipe::NamedPipe
     });
 
     // Reader
+    let (frames_tx, mut frames_rx) = mpsc::unbounded_channel::<Data>();
+    let limits = server.config().limits.clone();
+    tokio::spawn(read_frames(read_half, limits, frames_tx, tx));
+
+    let mut ticker = tokio::time::interval(TICK_INTERVAL);
+
     loop {
         if client.closed {
             break;
         }
 
-        // Expect a u32 indicating opcode
-        let opcode = OpCode::from_u32(read_half.read_u32_le().await?)?;
+        tokio::select! {
+            data = frames_rx.recv() => match data {
+                Some(data) => client.handle(data).await?,
+                None => break,
+            },
 
-        // Expect a u32 indicating length
-        let length = read_half.read_u32_le().await? as usize;
+            Some((name, data)) = upstream_rx.recv() => {
+                client.handle_upstream(&name, data).await?;
+            }
 
-        // Read the message
-        let mut buf = BytesMut::with_capacity(length);
-        let n = read_half.read_buf(&mut buf).await?;
+            Some(event) = events_rx.recv() => client.handle_event(event).await?,
 
-        if n == 0 {
-            break;
+            _ = ticker.tick() => client.tick()?,
         }
-
-        let msg = String::from_utf8(buf.to_vec())?;
-        tracing::trace!("Switch Reader: {}", msg);
-
-        let data = Data {
-            opcode,
-            msg
-        };
-
-        client.handle(data).await?;
     }
 
     client.disconnected();
@@ -0,0 +1,65 @@
+use std::time::{Duration, Instant};
+
+/// Counts frames a client sends each second
+#[derive(Debug)]
+pub struct FrameRate {
+    /// Frames allowed per second, or 0 for no limit
+    limit: u32,
+    window_start: Instant,
+    count: u32,
+}
+
+impl FrameRate {
+    pub fn new(limit: u32) -> FrameRate {
+        FrameRate {
+            limit,
+            window_start: Instant::now(),
+            count: 0,
+        }
+    }
+
+    /// Records a frame, returning whether it is within the limit
+    pub fn allow(&mut self) -> bool {
+        if self.limit == 0 {
+            return true;
+        }
+
+        if self.window_start.elapsed() >= Duration::from_secs(1) {
+            self.window_start = Instant::now();
+            self.count = 0;
+        }
+
+        self.count += 1;
+        self.count <= self.limit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_rate_limit() {
+        let mut rate = FrameRate::new(3);
+        assert!(rate.allow());
+        assert!(rate.allow());
+        assert!(rate.allow());
+        assert!(!rate.allow());
+    }
+
+    #[test]
+    fn frame_rate_window_resets() {
+        let mut rate = FrameRate::new(1);
+        assert!(rate.allow());
+        assert!(!rate.allow());
+
+        rate.window_start -= Duration::from_secs(1);
+        assert!(rate.allow());
+    }
+
+    #[test]
+    fn frame_rate_unlimited() {
+        let mut rate = FrameRate::new(0);
+        assert!((0..1000).all(|_| rate.allow()));
+    }
+}
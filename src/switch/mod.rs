@@ -1 +1,10 @@
+pub mod affinity;
+pub mod autoprofile;
+pub mod correlation;
 pub mod ipc;
+pub mod limits;
+pub mod policy;
+pub mod registry;
+pub mod schedule;
+pub mod throttle;
+pub mod validate;
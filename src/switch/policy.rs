@@ -0,0 +1,230 @@
+use crate::config::{self, Config};
+
+/// What the switch does with an application once it has handshaken
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Admission {
+    /// Relay to Discord as usual
+    Relay,
+    /// Refuse with a Close frame
+    Block,
+    /// Pretend to be Discord without relaying anything
+    Swallow,
+}
+
+/// Decides whether an application is relayed based on its `client_id`
+pub fn admit(applications: &config::Applications, client_id: &str) -> Admission {
+    let listed = |ids: &[String]| ids.iter().any(|id| id == client_id);
+
+    if listed(&applications.swallow) {
+        Admission::Swallow
+    } else if listed(&applications.block)
+        || (!applications.allow.is_empty() && !listed(&applications.allow))
+    {
+        Admission::Block
+    } else {
+        Admission::Relay
+    }
+}
+
+/// Decides whether a local peer may connect, which is our own user and those allowed by `peer`.
+/// `groups` are the peer's primary and supplementary groups.
+pub fn trust(peer: &config::Peer, own_uid: u32, uid: u32, groups: &[u32]) -> bool {
+    uid == own_uid || peer.uids.contains(&uid) || groups.iter().any(|gid| peer.gids.contains(gid))
+}
+
+/// Where a command is relayed
+#[derive(Clone, Debug, PartialEq)]
+pub enum Route {
+    /// Every connected Discord instance
+    All,
+    /// A single Discord instance
+    Instance(String),
+    /// Nowhere; the client receives an ERROR reply
+    Deny,
+}
+
+impl Route {
+    /// Whether the route reaches an instance
+    pub fn includes(&self, instance: &str) -> bool {
+        match self {
+            Route::All => true,
+            Route::Instance(name) => name == instance,
+            Route::Deny => false,
+        }
+    }
+}
+
+/// Whether a command reads data from the account or changes what the account shows
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kind {
+    Read,
+    Write,
+}
+
+pub fn classify(cmd: &str) -> Kind {
+    if cmd.starts_with("GET_") {
+        Kind::Read
+    } else {
+        Kind::Write
+    }
+}
+
+/// Decides which Discord instances receive a command
+///
+/// Read commands expect a single answer, so they go to the instance designated for the application.
+/// Commands outside the broadcast list can grant access to an account (e.g. `AUTHORIZE`), so they
+/// only reach the designated instance, if any.
+pub fn route(config: &Config, client_id: &str, cmd: &str) -> Route {
+    let commands = &config.commands;
+    let listed = |cmds: &[String]| cmds.iter().any(|c| c == cmd);
+
+    let instance = if listed(&commands.deny) {
+        None
+    } else if classify(cmd) == Kind::Read {
+        config.routing.read_instance(commands, client_id)
+    } else if listed(&commands.broadcast) {
+        return Route::All;
+    } else {
+        commands.instance.as_ref()
+    };
+
+    match instance {
+        Some(instance) => Route::Instance(instance.clone()),
+        None => Route::Deny,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trust_peers() {
+        let peer = config::Peer {
+            uids: vec![1001],
+            gids: vec![100],
+        };
+
+        assert!(trust(&peer, 1000, 1000, &[1000]));
+        assert!(trust(&peer, 1000, 1001, &[1001]));
+        assert!(trust(&peer, 1000, 1002, &[100]));
+        // A supplementary group is enough
+        assert!(trust(&peer, 1000, 1002, &[1002, 10, 100]));
+        assert!(!trust(&peer, 1000, 1002, &[1002, 10]));
+        assert!(!trust(&config::Peer::default(), 1000, 0, &[0]));
+    }
+
+    fn applications(allow: &[&str], block: &[&str], swallow: &[&str]) -> config::Applications {
+        let ids = |ids: &[&str]| ids.iter().map(|id| id.to_string()).collect();
+
+        config::Applications {
+            allow: ids(allow),
+            block: ids(block),
+            swallow: ids(swallow),
+        }
+    }
+
+    fn commands(instance: Option<&str>, deny: &[&str]) -> Config {
+        Config {
+            commands: config::Commands {
+                instance: instance.map(String::from),
+                deny: deny.iter().map(|cmd| cmd.to_string()).collect(),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn admit_by_default() {
+        let applications = applications(&[], &[], &[]);
+        assert_eq!(admit(&applications, "1"), Admission::Relay);
+    }
+
+    #[test]
+    fn admit_blocklist() {
+        let applications = applications(&[], &["1"], &[]);
+        assert_eq!(admit(&applications, "1"), Admission::Block);
+        assert_eq!(admit(&applications, "2"), Admission::Relay);
+    }
+
+    #[test]
+    fn admit_allowlist() {
+        let applications = applications(&["1"], &[], &[]);
+        assert_eq!(admit(&applications, "1"), Admission::Relay);
+        assert_eq!(admit(&applications, "2"), Admission::Block);
+    }
+
+    #[test]
+    fn admit_swallow_takes_precedence() {
+        let applications = applications(&["1"], &["2"], &["2", "3"]);
+        assert_eq!(admit(&applications, "2"), Admission::Swallow);
+        assert_eq!(admit(&applications, "3"), Admission::Swallow);
+    }
+
+    #[test]
+    fn route_presence_commands_everywhere() {
+        let commands = commands(None, &[]);
+        assert_eq!(route(&commands, "1", "SET_ACTIVITY"), Route::All);
+    }
+
+    #[test]
+    fn route_auth_commands_denied_by_default() {
+        let commands = commands(None, &[]);
+        assert_eq!(route(&commands, "1", "AUTHORIZE"), Route::Deny);
+        assert_eq!(route(&commands, "1", "AUTHENTICATE"), Route::Deny);
+    }
+
+    #[test]
+    fn route_auth_commands_to_instance() {
+        let commands = commands(Some("discord-ipc-1"), &[]);
+        assert_eq!(route(&commands, "1", "AUTHORIZE"), Route::Instance(String::from("discord-ipc-1")));
+    }
+
+    #[test]
+    fn route_deny_takes_precedence() {
+        let commands = commands(Some("discord-ipc-1"), &["SET_ACTIVITY", "AUTHORIZE"]);
+        assert_eq!(route(&commands, "1", "SET_ACTIVITY"), Route::Deny);
+        assert_eq!(route(&commands, "1", "AUTHORIZE"), Route::Deny);
+    }
+
+    #[test]
+    fn route_includes() {
+        assert!(Route::All.includes("discord-ipc-1"));
+        assert!(Route::Instance(String::from("discord-ipc-1")).includes("discord-ipc-1"));
+        assert!(!Route::Instance(String::from("discord-ipc-1")).includes("discord-ipc-2"));
+        assert!(!Route::Deny.includes("discord-ipc-1"));
+    }
+
+    #[test]
+    fn classify_commands() {
+        assert_eq!(classify("GET_GUILDS"), Kind::Read);
+        assert_eq!(classify("GET_SELECTED_VOICE_CHANNEL"), Kind::Read);
+        assert_eq!(classify("SET_ACTIVITY"), Kind::Write);
+        assert_eq!(classify("AUTHORIZE"), Kind::Write);
+    }
+
+    #[test]
+    fn route_read_commands_to_one_instance() {
+        let mut config = commands(Some("discord-ipc-1"), &[]);
+        assert_eq!(route(&config, "1", "GET_GUILDS"), Route::Instance(String::from("discord-ipc-1")));
+
+        config.routing.instance = Some(String::from("discord-ipc-2"));
+        config.routing.applications.insert(
+            String::from("2"),
+            config::ApplicationRouting {
+                instance: Some(String::from("discord-ipc-3")),
+                ..Default::default()
+            },
+        );
+        assert_eq!(route(&config, "1", "GET_CHANNEL"), Route::Instance(String::from("discord-ipc-2")));
+        assert_eq!(route(&config, "2", "GET_CHANNEL"), Route::Instance(String::from("discord-ipc-3")));
+        assert_eq!(route(&config, "2", "AUTHORIZE"), Route::Instance(String::from("discord-ipc-1")));
+    }
+
+    #[test]
+    fn route_read_commands_denied_without_instance() {
+        let config = commands(None, &[]);
+        assert_eq!(route(&config, "1", "GET_GUILDS"), Route::Deny);
+    }
+}
@@ -0,0 +1,426 @@
+use std::{collections::{HashMap, HashSet}, sync::{Mutex, MutexGuard}, time::Instant};
+
+use serde_json::Value;
+
+use tokio::sync::mpsc;
+
+use crate::{config::{Arbitration, Config}, discord::api::ApplicationRpcData, switch::{policy, schedule}};
+
+pub type SessionId = u64;
+
+/// Sent to a session when something outside of it changes what it should show
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    /// Re-evaluate which activity each Discord instance should show
+    Reconcile,
+    /// Close the session
+    Kick,
+    /// Decide again whether the application is relayed, blocked or swallowed
+    Readmit,
+    /// The application's metadata was looked up
+    Application(ApplicationRpcData),
+    /// The application's art assets were looked up, by name
+    Assets(HashSet<String>),
+}
+
+/// A connected RPC client
+#[derive(Clone, Debug)]
+pub struct Session {
+    pub client_id: Option<String>,
+    /// Application name looked up from Discord
+    pub name: Option<String>,
+    /// Process ID of the connected client, where the platform reports it
+    pub pid: Option<i32>,
+    /// Discord instances the session is connected to
+    pub upstreams: Vec<String>,
+    /// The current activity, if there is one
+    pub activity: Option<Value>,
+    /// When the current activity was set, if there is one
+    pub activity_at: Option<Instant>,
+    events: mpsc::UnboundedSender<Event>,
+}
+
+/// Server-wide registry of sessions, used to decide which one each Discord instance shows
+#[derive(Debug, Default)]
+pub struct Registry {
+    next: Mutex<SessionId>,
+    sessions: Mutex<HashMap<SessionId, Session>>,
+    /// Instances nothing is relayed to, until enabled again
+    disabled: Mutex<HashSet<String>>,
+    /// Instances no activity is shown on, until unmuted or the time given
+    muted: Mutex<HashMap<String, Option<Instant>>>,
+}
+
+impl Registry {
+    /// Adds a session unless `limit` sessions, if non-zero, are already connected. Checked and
+    /// added at once, so clients connecting together can't exceed it.
+    pub fn register(&self, events: mpsc::UnboundedSender<Event>, limit: usize) -> Option<SessionId> {
+        let mut sessions = self.sessions();
+        if limit != 0 && sessions.len() >= limit {
+            return None;
+        }
+
+        let id = {
+            let mut next = self.next.lock().unwrap_or_else(|e| e.into_inner());
+            *next += 1;
+            *next
+        };
+
+        sessions.insert(id, Session {
+            client_id: None,
+            name: None,
+            pid: None,
+            upstreams: Vec::new(),
+            activity: None,
+            activity_at: None,
+            events,
+        });
+
+        Some(id)
+    }
+
+    pub fn unregister(&self, id: SessionId) {
+        let session = self.sessions().remove(&id);
+        if session.is_some_and(|session| session.activity_at.is_some()) {
+            // Another session may be shown now
+            self.notify(id);
+        }
+    }
+
+    pub fn identify(&self, id: SessionId, client_id: &str) {
+        self.update(id, |session| session.client_id = Some(client_id.to_owned()));
+    }
+
+    /// Changes details of a session that don't affect what other sessions show
+    pub fn update(&self, id: SessionId, f: impl FnOnce(&mut Session)) {
+        if let Some(session) = self.sessions().get_mut(&id) {
+            f(session);
+        }
+    }
+
+    /// Copies of every session, ordered by ID
+    pub fn list(&self) -> Vec<(SessionId, Session)> {
+        let mut sessions = self.sessions().iter().map(|(id, session)| (*id, session.clone())).collect::<Vec<_>>();
+        sessions.sort_by_key(|(id, _)| *id);
+        sessions
+    }
+
+    /// Asks a session to close, returning whether it exists
+    pub fn kick(&self, id: SessionId) -> bool {
+        match self.sessions().get(&id) {
+            Some(session) => session.events.send(Event::Kick).is_ok(),
+            None => false,
+        }
+    }
+
+    pub fn enabled(&self, instance: &str) -> bool {
+        !self.disabled().contains(instance)
+    }
+
+    /// Enables or disables relaying to an instance, returning whether that changed anything
+    pub fn set_enabled(&self, instance: &str, enabled: bool) -> bool {
+        let changed = match enabled {
+            true => self.disabled().remove(instance),
+            false => self.disabled().insert(instance.to_owned()),
+        };
+
+        if changed {
+            self.reconcile_all();
+        }
+        changed
+    }
+
+    /// Records that a session set or cleared its activity and lets the others re-evaluate
+    pub fn set_activity(&self, id: SessionId, activity: Option<Value>) {
+        if let Some(session) = self.sessions().get_mut(&id) {
+            session.activity_at = activity.as_ref().map(|_| Instant::now());
+            session.activity = activity;
+        }
+
+        self.notify(id);
+    }
+
+    /// Decides which session's activity an instance shows, among those connected to it whose
+    /// activity the config currently routes there and doesn't withhold by schedule
+    pub fn winner(&self, config: &Config, instance: &str) -> Option<SessionId> {
+        let now = schedule::now();
+        let sessions = self.sessions();
+        let candidates = sessions.iter().filter_map(|(id, session)| {
+            let activity_at = session.activity_at?;
+            let client_id = session.client_id.as_deref().unwrap_or_default();
+            let eligible = session.upstreams.iter().any(|name| name == instance)
+                && policy::route(config, client_id, "SET_ACTIVITY").includes(instance)
+                && !schedule::application_quiet(config, client_id, now);
+
+            eligible.then_some(Candidate {
+                id: *id,
+                client_id,
+                activity_at,
+            })
+        });
+
+        arbitrate(config.arbitration(instance), candidates)
+    }
+
+    /// Lets every session re-evaluate, such as after the config changes
+    pub fn reconcile_all(&self) {
+        for session in self.sessions().values() {
+            let _ = session.events.send(Event::Reconcile);
+        }
+    }
+
+    /// Lets every session decide again whether it is admitted, such as after the profile changes
+    pub fn readmit_all(&self) {
+        for session in self.sessions().values() {
+            let _ = session.events.send(Event::Readmit);
+        }
+    }
+
+    /// Whether activity is withheld from an instance
+    pub fn muted(&self, instance: &str) -> bool {
+        self.muted_until(instance).is_some()
+    }
+
+    /// When an instance's mute ends, `Some(None)` if it lasts until unmuted, or `None` if it isn't
+    /// muted
+    pub fn muted_until(&self, instance: &str) -> Option<Option<Instant>> {
+        let until = *self.mutes().get(instance)?;
+        match until {
+            Some(until) if until <= Instant::now() => None,
+            until => Some(until),
+        }
+    }
+
+    /// Clears the activity shown on an instance and withholds it until `until`, or until unmuted
+    pub fn mute(&self, instance: &str, until: Option<Instant>) {
+        self.mutes().insert(instance.to_owned(), until);
+        self.reconcile_all();
+    }
+
+    /// Lets sessions show their activity on an instance again, returning whether it was muted
+    pub fn unmute(&self, instance: &str) -> bool {
+        let muted = self.mutes().remove(instance).is_some();
+        if muted {
+            self.reconcile_all();
+        }
+        muted
+    }
+
+    /// Ends a mute that was set to last until `until`, unless it has since been replaced
+    pub fn expire_mute(&self, instance: &str, until: Instant) {
+        let expired = {
+            let mut mutes = self.mutes();
+            let expired = mutes.get(instance) == Some(&Some(until));
+            if expired {
+                mutes.remove(instance);
+            }
+            expired
+        };
+
+        if expired {
+            tracing::info!("Mute of {} expired", instance);
+            self.reconcile_all();
+        }
+    }
+
+    /// Sends an event to every session except `from`
+    fn notify(&self, from: SessionId) {
+        for (id, session) in self.sessions().iter() {
+            if *id != from {
+                let _ = session.events.send(Event::Reconcile);
+            }
+        }
+    }
+
+    fn sessions(&self) -> MutexGuard<'_, HashMap<SessionId, Session>> {
+        self.sessions.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn disabled(&self) -> MutexGuard<'_, HashSet<String>> {
+        self.disabled.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn mutes(&self) -> MutexGuard<'_, HashMap<String, Option<Instant>>> {
+        self.muted.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// A session with an activity to show
+struct Candidate<'a> {
+    id: SessionId,
+    client_id: &'a str,
+    activity_at: Instant,
+}
+
+/// Picks the candidate an arbitration policy favors, breaking ties by the most recent activity
+fn arbitrate<'a>(arbitration: &Arbitration, candidates: impl Iterator<Item = Candidate<'a>>) -> Option<SessionId> {
+    let rank = |candidate: &Candidate| match arbitration {
+        Arbitration::Recent => 0,
+        Arbitration::Priority { applications } => applications
+            .iter()
+            .position(|client_id| client_id == candidate.client_id)
+            .unwrap_or(applications.len()),
+        Arbitration::Pinned { application } => usize::from(application != candidate.client_id),
+    };
+
+    candidates
+        .min_by(|a, b| rank(a).cmp(&rank(b)).then(b.activity_at.cmp(&a.activity_at)))
+        .map(|candidate| candidate.id)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn candidates(now: Instant) -> Vec<Candidate<'static>> {
+        vec![
+            Candidate { id: 1, client_id: "game", activity_at: now },
+            Candidate { id: 2, client_id: "music", activity_at: now + Duration::from_secs(1) },
+            Candidate { id: 3, client_id: "other", activity_at: now + Duration::from_secs(2) },
+        ]
+    }
+
+    #[test]
+    fn arbitrate_recent() {
+        let winner = arbitrate(&Arbitration::Recent, candidates(Instant::now()).into_iter());
+        assert_eq!(winner, Some(3));
+    }
+
+    #[test]
+    fn arbitrate_priority() {
+        let arbitration = Arbitration::Priority {
+            applications: vec![String::from("music"), String::from("game")],
+        };
+        let winner = arbitrate(&arbitration, candidates(Instant::now()).into_iter());
+        assert_eq!(winner, Some(2));
+
+        // Unlisted applications fall back to the most recent
+        let arbitration = Arbitration::Priority { applications: vec![] };
+        let winner = arbitrate(&arbitration, candidates(Instant::now()).into_iter());
+        assert_eq!(winner, Some(3));
+    }
+
+    #[test]
+    fn arbitrate_pinned() {
+        let arbitration = Arbitration::Pinned { application: String::from("game") };
+        let winner = arbitrate(&arbitration, candidates(Instant::now()).into_iter());
+        assert_eq!(winner, Some(1));
+
+        // The pinned application has no activity
+        let winner = arbitrate(&arbitration, candidates(Instant::now()).into_iter().skip(1));
+        assert_eq!(winner, Some(3));
+    }
+
+    #[test]
+    fn arbitrate_nothing() {
+        assert_eq!(arbitrate(&Arbitration::Recent, std::iter::empty()), None);
+    }
+
+    #[test]
+    fn registry_winner_and_notify() {
+        let registry = Registry::default();
+        let config = Config::default();
+        let activity = Some(serde_json::json!({ "state": "Playing" }));
+
+        let (tx, mut rx1) = mpsc::unbounded_channel();
+        let first = registry.register(tx, 0).unwrap();
+        let (tx, mut rx2) = mpsc::unbounded_channel();
+        let second = registry.register(tx, 0).unwrap();
+        for id in [first, second] {
+            registry.update(id, |session| session.upstreams = vec![String::from("discord-ipc-1")]);
+        }
+
+        registry.set_activity(first, activity.clone());
+        assert_eq!(registry.winner(&config, "discord-ipc-1"), Some(first));
+        assert_eq!(registry.winner(&config, "discord-ipc-2"), None);
+        assert!(rx1.try_recv().is_err());
+        assert_eq!(rx2.try_recv().unwrap(), Event::Reconcile);
+
+        registry.set_activity(second, activity.clone());
+        assert_eq!(registry.winner(&config, "discord-ipc-1"), Some(second));
+        assert_eq!(rx1.try_recv().unwrap(), Event::Reconcile);
+
+        // The previous activity is restored once the winner leaves
+        registry.unregister(second);
+        assert_eq!(registry.winner(&config, "discord-ipc-1"), Some(first));
+
+        // Activity the config no longer routes to an instance isn't shown there
+        let config = Config::parse("[commands]\ndeny = [\"SET_ACTIVITY\"]").unwrap();
+        assert_eq!(registry.winner(&config, "discord-ipc-1"), None);
+    }
+
+    #[test]
+    fn registry_session_limit() {
+        let registry = Registry::default();
+        let (tx, _rx) = mpsc::unbounded_channel();
+
+        let first = registry.register(tx.clone(), 2).unwrap();
+        assert!(registry.register(tx.clone(), 2).is_some());
+        assert!(registry.register(tx.clone(), 2).is_none());
+        assert_eq!(registry.list().len(), 2);
+
+        // A session leaving frees its slot
+        registry.unregister(first);
+        assert!(registry.register(tx.clone(), 2).is_some());
+        assert!(registry.register(tx, 0).is_some());
+    }
+
+    #[test]
+    fn registry_enable_and_kick() {
+        let registry = Registry::default();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let id = registry.register(tx, 0).unwrap();
+
+        assert!(registry.set_enabled("discord-ipc-1", false));
+        assert!(!registry.set_enabled("discord-ipc-1", false));
+        assert!(!registry.enabled("discord-ipc-1"));
+        assert_eq!(rx.try_recv().unwrap(), Event::Reconcile);
+
+        assert!(registry.set_enabled("discord-ipc-1", true));
+        assert!(registry.enabled("discord-ipc-1"));
+        assert_eq!(rx.try_recv().unwrap(), Event::Reconcile);
+
+        assert!(registry.kick(id));
+        assert_eq!(rx.try_recv().unwrap(), Event::Kick);
+        assert!(!registry.kick(id + 1));
+    }
+
+    #[test]
+    fn registry_mute() {
+        let registry = Registry::default();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        registry.register(tx, 0);
+
+        registry.mute("discord-ipc-1", None);
+        assert!(registry.muted("discord-ipc-1"));
+        assert_eq!(registry.muted_until("discord-ipc-1"), Some(None));
+        assert_eq!(rx.try_recv().unwrap(), Event::Reconcile);
+
+        assert!(registry.unmute("discord-ipc-1"));
+        assert!(!registry.unmute("discord-ipc-1"));
+        assert_eq!(rx.try_recv().unwrap(), Event::Reconcile);
+
+        // Passed expiries no longer count
+        registry.mute("discord-ipc-1", Some(Instant::now() - Duration::from_secs(1)));
+        assert!(!registry.muted("discord-ipc-1"));
+    }
+
+    #[test]
+    fn registry_mute_expiry_is_not_stale() {
+        let registry = Registry::default();
+        let first = Instant::now() + Duration::from_secs(60);
+        let second = first + Duration::from_secs(60);
+
+        registry.mute("discord-ipc-1", Some(first));
+        registry.mute("discord-ipc-1", Some(second));
+
+        // The first mute's timer fires after it was replaced
+        registry.expire_mute("discord-ipc-1", first);
+        assert!(registry.muted("discord-ipc-1"));
+
+        registry.expire_mute("discord-ipc-1", second);
+        assert!(!registry.muted("discord-ipc-1"));
+    }
+}
@@ -0,0 +1,121 @@
+use std::{collections::BTreeSet, time::Duration};
+
+use chrono::{Local, NaiveDateTime, Timelike};
+
+use crate::{config::{Config, Window}, switch::ipc::Server};
+
+/// The current local time, which windows are compared against
+pub fn now() -> NaiveDateTime {
+    Local::now().naive_local()
+}
+
+/// Whether an instance's `quiet` or `hours` withhold activity from it at a time
+pub fn instance_quiet(config: &Config, instance: &str, at: NaiveDateTime) -> bool {
+    let Some(instance) = config.instances.get(instance) else {
+        return false;
+    };
+
+    within(&instance.quiet, at) || (!instance.hours.is_empty() && !within(&instance.hours, at))
+}
+
+/// Whether an application's activity is withheld from every instance at a time
+pub fn application_quiet(config: &Config, client_id: &str, at: NaiveDateTime) -> bool {
+    config.routing.applications.get(client_id).is_some_and(|app| within(&app.quiet, at))
+}
+
+fn within(windows: &[Window], at: NaiveDateTime) -> bool {
+    windows.iter().any(|window| window.contains(at))
+}
+
+/// What the schedule withholds at a time
+#[derive(Debug, Default, PartialEq)]
+struct Quiet {
+    instances: BTreeSet<String>,
+    applications: BTreeSet<String>,
+}
+
+impl Quiet {
+    fn at(config: &Config, at: NaiveDateTime) -> Quiet {
+        Quiet {
+            instances: config.instances.keys().filter(|name| instance_quiet(config, name, at)).cloned().collect(),
+            applications: config.routing.applications.keys().filter(|id| application_quiet(config, id, at)).cloned().collect(),
+        }
+    }
+
+    /// Logs windows that started or ended since `previous`
+    fn log_changes(&self, previous: &Quiet) {
+        for name in self.instances.difference(&previous.instances) {
+            tracing::info!("Quiet hours started on {}", name);
+        }
+        for name in previous.instances.difference(&self.instances) {
+            tracing::info!("Quiet hours ended on {}", name);
+        }
+        for id in self.applications.difference(&previous.applications) {
+            tracing::info!("Quiet hours started for application {}", id);
+        }
+        for id in previous.applications.difference(&self.applications) {
+            tracing::info!("Quiet hours ended for application {}", id);
+        }
+    }
+}
+
+/// Re-evaluates every session's activity whenever a window starts or ends, until the server is
+/// cancelled. Windows are given to the minute, so they are checked at the start of each one.
+pub async fn watch(server: Server) {
+    let mut quiet = Quiet::at(&server.config(), now());
+
+    loop {
+        let now = Local::now();
+        let elapsed = Duration::from_secs(now.second().into()) + Duration::from_nanos(now.nanosecond().into());
+        let until_minute = Duration::from_secs(60).saturating_sub(elapsed);
+
+        tokio::select! {
+            _ = server.token().cancelled() => break,
+            _ = tokio::time::sleep(until_minute) => {}
+        }
+
+        let current = Quiet::at(&server.config(), self::now());
+        if current != quiet {
+            current.log_changes(&quiet);
+            quiet = current;
+            server.registry().reconcile_all();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+
+    #[test]
+    fn quiet_instances_and_applications() {
+        let config = Config::parse(
+            r#"
+            [instances.discord-ipc-1]
+            hours = [{ start = "09:00", end = "17:00" }]
+            [instances.discord-ipc-2]
+            quiet = [{ start = "09:00", end = "17:00" }]
+            [routing.applications.123]
+            quiet = [{ start = "12:00", end = "13:00" }]
+            "#,
+        )
+        .unwrap();
+        let at = |hour| NaiveDate::from_ymd_opt(2026, 10, 19).unwrap().and_hms_opt(hour, 30, 0).unwrap();
+
+        assert!(instance_quiet(&config, "discord-ipc-1", at(8)));
+        assert!(!instance_quiet(&config, "discord-ipc-2", at(8)));
+        assert!(!instance_quiet(&config, "discord-ipc-1", at(10)));
+        assert!(instance_quiet(&config, "discord-ipc-2", at(10)));
+        assert!(!instance_quiet(&config, "discord-ipc-3", at(10)));
+
+        assert!(application_quiet(&config, "123", at(12)));
+        assert!(!application_quiet(&config, "123", at(13)));
+        assert!(!application_quiet(&config, "456", at(12)));
+
+        let quiet = Quiet::at(&config, at(12));
+        assert_eq!(quiet.instances, BTreeSet::from([String::from("discord-ipc-2")]));
+        assert_eq!(quiet.applications, BTreeSet::from([String::from("123")]));
+    }
+}
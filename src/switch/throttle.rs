@@ -0,0 +1,84 @@
+use std::{collections::{HashMap, HashSet}, time::{Duration, Instant}};
+
+/// What to do with an activity update for an instance
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Admit {
+    /// Send it now
+    Now,
+    /// Hold it until the window closes, after the given delay
+    Schedule(Duration),
+    /// Hold it; a flush is already scheduled
+    Hold,
+}
+
+/// Limits activity updates to one per window on each Discord instance, holding newer ones so only
+/// the latest is sent when the window closes
+#[derive(Debug, Default)]
+pub struct Throttle {
+    sent_at: HashMap<String, Instant>,
+    held: HashSet<String>,
+}
+
+impl Throttle {
+    /// Decides whether an update to `instance` may be sent, recording it as sent if so
+    pub fn admit(&mut self, instance: &str, window: Duration) -> Admit {
+        let now = Instant::now();
+
+        if let Some(sent_at) = self.sent_at.get(instance)
+            && let Some(wait) = (*sent_at + window).checked_duration_since(now)
+            && !wait.is_zero()
+        {
+            return match self.held.insert(instance.to_owned()) {
+                true => Admit::Schedule(wait),
+                false => Admit::Hold,
+            };
+        }
+
+        self.sent_at.insert(instance.to_owned(), now);
+        self.held.remove(instance);
+        Admit::Now
+    }
+
+    /// Drops a held update that no longer needs sending
+    pub fn release(&mut self, instance: &str) {
+        self.held.remove(instance);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn first_update_is_sent() {
+        let mut throttle = Throttle::default();
+        assert_eq!(throttle.admit("discord-ipc-1", WINDOW), Admit::Now);
+        assert_eq!(throttle.admit("discord-ipc-2", WINDOW), Admit::Now);
+    }
+
+    #[test]
+    fn updates_within_window_are_held() {
+        let mut throttle = Throttle::default();
+        assert_eq!(throttle.admit("discord-ipc-1", WINDOW), Admit::Now);
+        assert!(matches!(throttle.admit("discord-ipc-1", WINDOW), Admit::Schedule(wait) if wait <= WINDOW));
+        assert_eq!(throttle.admit("discord-ipc-1", WINDOW), Admit::Hold);
+    }
+
+    #[test]
+    fn released_update_schedules_again() {
+        let mut throttle = Throttle::default();
+        throttle.admit("discord-ipc-1", WINDOW);
+        throttle.admit("discord-ipc-1", WINDOW);
+        throttle.release("discord-ipc-1");
+        assert!(matches!(throttle.admit("discord-ipc-1", WINDOW), Admit::Schedule(_)));
+    }
+
+    #[test]
+    fn no_window() {
+        let mut throttle = Throttle::default();
+        assert_eq!(throttle.admit("discord-ipc-1", Duration::ZERO), Admit::Now);
+        assert_eq!(throttle.admit("discord-ipc-1", Duration::ZERO), Admit::Now);
+    }
+}
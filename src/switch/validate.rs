@@ -0,0 +1,322 @@
+use std::collections::HashSet;
+
+use serde_json::{Map, Value};
+
+/// Length Discord requires of activity text such as `details` and `state`
+const TEXT_LENGTH: (usize, usize) = (2, 128);
+/// Longest party ID or secret
+const ID_LENGTH: usize = 128;
+/// Longest asset key or image URL
+const ASSET_LENGTH: usize = 256;
+const BUTTONS: usize = 2;
+const BUTTON_LABEL_LENGTH: (usize, usize) = (1, 32);
+const BUTTON_URL_LENGTH: usize = 512;
+
+/// Checks an activity against the limits Discord enforces, returning a description of each
+/// problem found. With `fix`, each problem is also repaired: text that is too long is truncated
+/// and anything else invalid is removed. An activity that isn't an object can't be repaired.
+pub fn activity(activity: &mut Value, fix: bool) -> Result<Vec<String>, String> {
+    let activity = match activity {
+        Value::Null => return Ok(Vec::new()),
+        Value::Object(activity) => activity,
+        _ => return Err(String::from("activity must be an object")),
+    };
+    let mut check = Check { fix, problems: Vec::new() };
+
+    check.text(activity, "details", "details", TEXT_LENGTH);
+    check.text(activity, "state", "state", TEXT_LENGTH);
+    check.object(activity, "timestamps", |check, timestamps| check.timestamps(timestamps));
+    check.object(activity, "assets", |check, assets| check.assets(assets));
+    check.object(activity, "party", |check, party| check.party(party));
+    check.object(activity, "secrets", |check, secrets| {
+        for key in ["join", "spectate", "match"] {
+            check.id(secrets, key, &format!("secrets.{}", key));
+        }
+    });
+    check.buttons(activity);
+
+    Ok(check.problems)
+}
+
+struct Check {
+    fix: bool,
+    problems: Vec<String>,
+}
+
+impl Check {
+    /// Records a problem, returning whether it should be repaired
+    fn problem(&mut self, problem: String) -> bool {
+        self.problems.push(problem);
+        self.fix
+    }
+
+    /// Checks a nested object, which is removed if it is anything else
+    fn object(&mut self, map: &mut Map<String, Value>, key: &str, check: impl FnOnce(&mut Check, &mut Map<String, Value>)) {
+        match map.get_mut(key) {
+            None | Some(Value::Null) => {}
+            Some(Value::Object(object)) => check(self, object),
+            Some(_) => {
+                if self.problem(format!("{} must be an object", key)) {
+                    map.remove(key);
+                }
+            }
+        }
+    }
+
+    /// Checks text is a string of an allowed length, truncating it if too long
+    fn text(&mut self, map: &mut Map<String, Value>, key: &str, path: &str, (min, max): (usize, usize)) {
+        let length = match map.get(key) {
+            None | Some(Value::Null) => return,
+            Some(Value::String(text)) => text.chars().count(),
+            Some(_) => {
+                if self.problem(format!("{} must be a string", path)) {
+                    map.remove(key);
+                }
+                return;
+            }
+        };
+
+        if length > max && self.problem(format!("{} is longer than {} characters", path, max)) {
+            let text = map[key].as_str().unwrap_or_default().chars().take(max).collect::<String>();
+            map.insert(key.to_owned(), Value::String(text));
+        } else if length < min && self.problem(format!("{} is shorter than {} characters", path, min)) {
+            map.remove(key);
+        }
+    }
+
+    /// Checks an ID or secret, which is removed rather than truncated as a shortened one is useless
+    fn id(&mut self, map: &mut Map<String, Value>, key: &str, path: &str) {
+        let valid = match map.get(key) {
+            None | Some(Value::Null) => return,
+            Some(Value::String(id)) => id.chars().count() <= ID_LENGTH,
+            Some(_) => false,
+        };
+
+        if !valid && self.problem(format!("{} must be a string of at most {} characters", path, ID_LENGTH)) {
+            map.remove(key);
+        }
+    }
+
+    fn timestamps(&mut self, timestamps: &mut Map<String, Value>) {
+        for key in ["start", "end"] {
+            let valid = timestamps.get(key).is_none_or(|timestamp| timestamp.is_null() || timestamp.is_u64());
+            if !valid && self.problem(format!("timestamps.{} must be a non-negative integer", key)) {
+                timestamps.remove(key);
+            }
+        }
+
+        let start = timestamps.get("start").and_then(Value::as_u64);
+        let end = timestamps.get("end").and_then(Value::as_u64);
+        if let (Some(start), Some(end)) = (start, end)
+            && end < start
+            && self.problem(String::from("timestamps.end is before timestamps.start"))
+        {
+            timestamps.remove("end");
+        }
+    }
+
+    fn assets(&mut self, assets: &mut Map<String, Value>) {
+        for key in ["large_image", "small_image"] {
+            let valid = match assets.get(key) {
+                None | Some(Value::Null) => continue,
+                Some(Value::String(image)) => asset_key(image),
+                Some(_) => false,
+            };
+
+            if !valid && self.problem(format!("assets.{} is not an asset key or image URL", key)) {
+                assets.remove(key);
+            }
+        }
+
+        for key in ["large_text", "small_text"] {
+            self.text(assets, key, &format!("assets.{}", key), TEXT_LENGTH);
+        }
+    }
+
+    fn party(&mut self, party: &mut Map<String, Value>) {
+        self.id(party, "id", "party.id");
+
+        let valid = match party.get("size") {
+            None | Some(Value::Null) => return,
+            Some(Value::Array(size)) => match size.as_slice() {
+                [current, max] => matches!((current.as_u64(), max.as_u64()), (Some(current), Some(max)) if current >= 1 && current <= max),
+                _ => false,
+            },
+            Some(_) => false,
+        };
+
+        if !valid && self.problem(String::from("party.size must be [current, max] with 1 <= current <= max")) {
+            party.remove("size");
+        }
+    }
+
+    fn buttons(&mut self, activity: &mut Map<String, Value>) {
+        let has_secrets = activity.get("secrets").and_then(Value::as_object).is_some_and(|secrets| !secrets.is_empty());
+        let buttons = match activity.get_mut("buttons") {
+            None | Some(Value::Null) => return,
+            Some(Value::Array(buttons)) if !has_secrets => buttons,
+            Some(Value::Array(_)) => {
+                if self.problem(String::from("buttons can't be used with secrets")) {
+                    activity.remove("buttons");
+                }
+                return;
+            }
+            Some(_) => {
+                if self.problem(String::from("buttons must be an array")) {
+                    activity.remove("buttons");
+                }
+                return;
+            }
+        };
+
+        if buttons.len() > BUTTONS && self.problem(format!("more than {} buttons", BUTTONS)) {
+            buttons.truncate(BUTTONS);
+        }
+
+        let mut i = 0;
+        buttons.retain_mut(|button| {
+            let path = format!("buttons[{}]", i);
+            i += 1;
+
+            let Some(button) = button.as_object_mut() else {
+                return !self.problem(format!("{} must be an object", path));
+            };
+
+            let url_valid = button.get("url").and_then(Value::as_str).is_some_and(button_url);
+            if !url_valid && self.problem(format!("{}.url must be an http(s) URL of at most {} characters", path, BUTTON_URL_LENGTH)) {
+                return false;
+            }
+
+            if !button.get("label").is_some_and(Value::is_string) {
+                return !self.problem(format!("{}.label must be a string", path));
+            }
+            self.text(button, "label", &format!("{}.label", path), BUTTON_LABEL_LENGTH);
+            // Too short a label is removed, which leaves the button unusable
+            button.contains_key("label")
+        });
+    }
+}
+
+/// Image keys in an activity's assets that aren't among an application's `known` asset names, as
+/// `(field, key)`. Image URLs are never unknown.
+pub fn unknown_assets(activity: &Value, known: &HashSet<String>) -> Vec<(&'static str, String)> {
+    ["large_image", "small_image"]
+        .into_iter()
+        .filter_map(|field| {
+            let key = activity.get("assets")?.get(field)?.as_str()?;
+            (!image_url(key) && !known.contains(&key.to_lowercase())).then(|| (field, key.to_owned()))
+        })
+        .collect()
+}
+
+/// Whether an image is given by URL rather than by asset key
+fn image_url(image: &str) -> bool {
+    image.starts_with("https://") || image.starts_with("http://") || image.starts_with("mp:")
+}
+
+/// Whether an image is an uploaded asset's key or an image URL
+fn asset_key(image: &str) -> bool {
+    if image.is_empty() || image.len() > ASSET_LENGTH {
+        return false;
+    }
+    if image_url(image) {
+        return !image.chars().any(char::is_whitespace);
+    }
+
+    image.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+}
+
+fn button_url(url: &str) -> bool {
+    url.len() <= BUTTON_URL_LENGTH
+        && (url.starts_with("https://") || url.starts_with("http://"))
+        && !url.chars().any(char::is_whitespace)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn invalid() -> Value {
+        json!({
+            "details": "d".repeat(200),
+            "state": "s",
+            "timestamps": { "start": 200, "end": 100 },
+            "assets": { "large_image": "has spaces", "small_image": "mp:external/abc", "large_text": 3 },
+            "party": { "id": "party", "size": [5, 4] },
+            "buttons": [
+                { "label": "Join", "url": "https://example.com" },
+                { "label": "x".repeat(40), "url": "https://example.com/2" },
+                { "label": "Third", "url": "https://example.com/3" },
+            ],
+        })
+    }
+
+    #[test]
+    fn valid_activity() {
+        let mut activity = json!({
+            "details": "Competitive",
+            "state": "In a match",
+            "timestamps": { "start": 1700000000 },
+            "assets": { "large_image": "map_dust2", "large_text": "Dust II" },
+            "party": { "id": "ae488379", "size": [1, 5] },
+            "buttons": [{ "label": "Watch", "url": "https://example.com" }],
+        });
+        let original = activity.clone();
+
+        assert_eq!(super::activity(&mut activity, true), Ok(Vec::new()));
+        assert_eq!(activity, original);
+        assert_eq!(super::activity(&mut Value::Null, false), Ok(Vec::new()));
+    }
+
+    #[test]
+    fn report_problems() {
+        let mut activity = invalid();
+        let problems = super::activity(&mut activity, false).unwrap();
+
+        assert_eq!(problems.len(), 8, "{:?}", problems);
+        assert!(problems.contains(&String::from("details is longer than 128 characters")));
+        assert!(problems.contains(&String::from("buttons[1].label is longer than 32 characters")));
+        // Without fix nothing changes
+        assert_eq!(activity, invalid());
+
+        assert!(super::activity(&mut json!("playing"), true).is_err());
+    }
+
+    #[test]
+    fn fix_problems() {
+        let mut activity = invalid();
+        super::activity(&mut activity, true).unwrap();
+
+        assert_eq!(activity["details"].as_str().unwrap().len(), 128);
+        assert!(activity.get("state").is_none());
+        assert_eq!(activity["timestamps"], json!({ "start": 200 }));
+        assert_eq!(activity["assets"], json!({ "small_image": "mp:external/abc" }));
+        assert_eq!(activity["party"], json!({ "id": "party" }));
+        assert_eq!(activity["buttons"].as_array().unwrap().len(), 2);
+        assert_eq!(activity["buttons"][1]["label"].as_str().unwrap().len(), 32);
+
+        assert_eq!(super::activity(&mut activity, false), Ok(Vec::new()));
+    }
+
+    #[test]
+    fn find_unknown_assets() {
+        let known = HashSet::from([String::from("logo"), String::from("map_dust2")]);
+        let activity = json!({ "assets": { "large_image": "map_dust", "small_image": "Logo" } });
+        assert_eq!(unknown_assets(&activity, &known), vec![("large_image", String::from("map_dust"))]);
+
+        let activity = json!({ "assets": { "large_image": "https://example.com/a.png", "small_image": "mp:external/abc" } });
+        assert!(unknown_assets(&activity, &known).is_empty());
+        assert!(unknown_assets(&json!({ "state": "Idle" }), &known).is_empty());
+    }
+
+    #[test]
+    fn buttons_with_secrets() {
+        let mut activity = json!({ "secrets": { "join": "abc" }, "buttons": [{ "label": "Join", "url": "https://example.com" }] });
+        super::activity(&mut activity, true).unwrap();
+
+        assert!(activity.get("buttons").is_none());
+        assert_eq!(activity["secrets"]["join"], "abc");
+    }
+}